@@ -0,0 +1,211 @@
+//! Static libraries: named object modules bundled into one file.
+//!
+//! The serialized form is little-endian throughout:
+//!
+//! ```text
+//! magic    "SFTA"
+//! version  u16
+//! modules  u16 count, each: u16 name length, UTF-8 name, u32 object length, object file
+//! ```
+
+use std::convert::TryFrom;
+
+use crate::object::{self, Object, Reader};
+
+pub const MAGIC: [u8; 4] = *b"SFTA";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("not a library archive")]
+    BadMagic,
+    #[error("unsupported library archive version {0}")]
+    UnsupportedVersion(u16),
+    #[error("library archive is truncated")]
+    Truncated,
+    #[error("library archive contains a module name that is not UTF-8")]
+    InvalidName,
+    #[error("library archive has {0} bytes of trailing data")]
+    TrailingData(usize),
+    #[error("{0} does not fit in a library archive")]
+    TooLarge(&'static str),
+    #[error("module {module} in library archive: {source}")]
+    Object {
+        module: String,
+        source: object::Error,
+    },
+}
+
+/// A library of object modules. The linker only includes the modules that define a symbol
+/// that is still unresolved.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Archive {
+    pub modules: Vec<Module>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Module {
+    pub name: String,
+    pub object: Object,
+}
+
+impl Archive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a module, replacing any existing module with the same name.
+    pub fn add(&mut self, name: &str, object: Object) {
+        let module = Module {
+            name: name.to_owned(),
+            object,
+        };
+        match self.modules.iter_mut().find(|m| m.name == name) {
+            Some(existing) => *existing = module,
+            None => self.modules.push(module),
+        }
+    }
+
+    pub fn module(&self, name: &str) -> Option<&Object> {
+        self.modules
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| &m.object)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(&VERSION.to_le_bytes());
+        let count =
+            u16::try_from(self.modules.len()).map_err(|_| Error::TooLarge("module list"))?;
+        bytes.extend(&count.to_le_bytes());
+        for module in &self.modules {
+            let name_len =
+                u16::try_from(module.name.len()).map_err(|_| Error::TooLarge("module name"))?;
+            bytes.extend(&name_len.to_le_bytes());
+            bytes.extend(module.name.as_bytes());
+            let object = module.object.to_bytes().map_err(|source| Error::Object {
+                module: module.name.clone(),
+                source,
+            })?;
+            let object_len = u32::try_from(object.len()).map_err(|_| Error::TooLarge("module"))?;
+            bytes.extend(&object_len.to_le_bytes());
+            bytes.extend(object);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len()).map_err(read_error)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = reader.u16().map_err(read_error)?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let count = reader.u16().map_err(read_error)?;
+        let mut archive = Archive::new();
+        for _ in 0..count {
+            let name = reader.string().map_err(read_error)?;
+            let object_len = reader.u32().map_err(read_error)? as usize;
+            let object = reader.take(object_len).map_err(read_error)?;
+            let object = Object::from_bytes(object).map_err(|source| Error::Object {
+                module: name.clone(),
+                source,
+            })?;
+            archive.modules.push(Module { name, object });
+        }
+        if !reader.0.is_empty() {
+            return Err(Error::TrailingData(reader.0.len()));
+        }
+        Ok(archive)
+    }
+}
+
+/// The archive error for an error reading its own fields, rather than a module's object.
+fn read_error(error: object::Error) -> Error {
+    match error {
+        object::Error::InvalidString => Error::InvalidName,
+        _ => Error::Truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{ObjectSegment, Symbol};
+
+    fn object(symbol: &str) -> Object {
+        Object {
+            segments: vec![ObjectSegment {
+                name: "CODE".to_owned(),
+                org: None,
+                bytes: vec![0x60],
+                relocations: vec![],
+            }],
+            symbols: vec![Symbol {
+                name: symbol.to_owned(),
+                segment: "CODE".to_owned(),
+                offset: 0,
+                exported: true,
+            }],
+            imports: vec![],
+            lines: vec![],
+            entry: None,
+            inits: vec![],
+            checks: vec![],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut archive = Archive::new();
+        archive.add("mul.o", object("mul"));
+        archive.add("div.o", object("div"));
+        let bytes = archive.to_bytes().unwrap();
+        assert_eq!(Ok(archive), Archive::from_bytes(&bytes))
+    }
+
+    #[test]
+    fn add_replaces() {
+        let mut archive = Archive::new();
+        archive.add("mul.o", object("mul"));
+        archive.add("mul.o", object("mul16"));
+        assert_eq!(1, archive.modules.len());
+        assert_eq!(Some(&object("mul16")), archive.module("mul.o"))
+    }
+
+    #[test]
+    fn bad_module() {
+        let mut archive = Archive::new();
+        archive.add("mul.o", object("mul"));
+        let mut bytes = archive.to_bytes().unwrap();
+        let len = bytes.len();
+        bytes[len - 1] = 0xFF;
+        assert!(matches!(
+            Archive::from_bytes(&bytes),
+            Err(Error::Object { module, .. }) if module == "mul.o"
+        ))
+    }
+
+    #[test]
+    fn invalid_name() {
+        let mut archive = Archive::new();
+        archive.add("mul.o", object("mul"));
+        let mut bytes = archive.to_bytes().unwrap();
+        bytes[10] = 0xFF;
+        assert_eq!(Err(Error::InvalidName), Archive::from_bytes(&bytes))
+    }
+
+    #[test]
+    fn truncated() {
+        let mut archive = Archive::new();
+        archive.add("mul.o", object("mul"));
+        let bytes = archive.to_bytes().unwrap();
+        assert_eq!(
+            Err(Error::Truncated),
+            Archive::from_bytes(&bytes[..bytes.len() - 1])
+        )
+    }
+}
@@ -1,149 +1,955 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::str::FromStr;
 
-use crate::parser::{Instruction, OperandExpression};
+use crate::cpu::{Cpu, Cycles};
+use crate::diagnostic::{Diagnostic, Severity, Span};
+use crate::linker::DEFAULT_SEGMENT;
+use crate::lint::{Level, Lint, Lints, Warning};
+use crate::object::{
+    AddressSize, Check, CheckKind, Import, Line, Object, ObjectSegment, Relocation, RelocationKind,
+    RelocationTarget, Symbol,
+};
+use crate::parser::{Directive, Instruction, Mnemonic, OperandExpression};
 
-use super::parser::{Element, Parsed};
+use super::parser::{Element, Located, Parsed};
 
-// TODO multiple errors?
-#[derive(Debug)]
-pub enum Error {}
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("symbol {0} is not defined or imported")]
+    UndefinedSymbol(String, Span),
+    #[error("symbol {0} is exported but not defined")]
+    UndefinedExport(String, Span),
+    #[error("symbol {0} is both imported and defined")]
+    ImportedSymbolDefined(String, Span),
+    #[error("label {0} is already defined")]
+    DuplicateLabel(String, Span),
+    #[error("the program counter runs past $FFFF")]
+    ProgramCounterOverflow(Span),
+    #[error("{0} is at {1:#06X}, which does not fit in one byte")]
+    OperandOutOfRange(String, u16, Span),
+    #[error("branch target {0} is {1} bytes away, more than a branch can reach")]
+    BranchOutOfRange(String, i32, Span),
+    #[error("branch to {0:#06X} from code that has no fixed address")]
+    AbsoluteBranch(u16, Span),
+    #[error("{0} is not available on the {1}")]
+    UnsupportedInstruction(Mnemonic, Cpu, Span),
+    #[error("the block takes {0} to {1} cycles, outside the budget of {2} to {3}")]
+    CycleBudget(u32, u32, u32, u32, Span),
+    #[error(".cycles without a matching .endcycles, or the other way around")]
+    UnmatchedCycles(Span),
+}
+
+impl Error {
+    pub fn span(&self) -> Span {
+        match self {
+            Error::UndefinedSymbol(_, span)
+            | Error::UndefinedExport(_, span)
+            | Error::ImportedSymbolDefined(_, span)
+            | Error::DuplicateLabel(_, span)
+            | Error::ProgramCounterOverflow(span)
+            | Error::OperandOutOfRange(_, _, span)
+            | Error::BranchOutOfRange(_, _, span)
+            | Error::AbsoluteBranch(_, span)
+            | Error::UnsupportedInstruction(_, _, span)
+            | Error::CycleBudget(_, _, _, _, span)
+            | Error::UnmatchedCycles(span) => *span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            message: self.to_string(),
+            span: self.span(),
+            severity: Severity::Error,
+            module: 0,
+        }
+    }
+}
 
 enum EmitResult {
     FullyDetermined(Vec<u8>),
-    PartiallyUnknown(Instruction),
+    /// The relocations' offsets are relative to the start of the bytes.
+    Relocatable(Vec<u8>, Vec<Relocation>),
+    PartiallyUnknown(Pending),
     NoBytesRequired,
 }
 
+/// Output that refers to a label that is not known yet, emitted again in the last pass.
+enum Pending {
+    /// An instruction starting at `offset` in its segment.
+    Instruction {
+        instruction: Instruction,
+        offset: u16,
+    },
+    /// A BASIC `SYS` line starting at `offset` in its segment.
+    BasicStub {
+        target: String,
+        offset: u16,
+    },
+    Vectors(Vec<String>),
+}
+
+/// Labels that are still unknown in the last pass have to be imported from other modules.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Pass {
+    First,
+    Last,
+}
+
+/// A `.cycles` block that is still open, with the cycles of its instructions so far.
+#[derive(Debug)]
+struct CycleBlock {
+    min: u32,
+    max: u32,
+    best: u32,
+    worst: u32,
+    /// Where the block's `.cycles` is.
+    span: Span,
+}
+
 #[derive(Debug)]
 struct GenerationState {
-    program_counter: u16,
-    label_locations: HashMap<String, u16>,
+    current_segment: String,
+    /// Offsets from the start of this module's fragment of each segment.
+    program_counters: HashMap<String, u16>,
+    label_locations: HashMap<String, (String, u16)>,
+    /// Where each label's name is in the source.
+    label_spans: HashMap<String, Span>,
+    /// Imported symbols, with their size and where they are imported.
+    imports: HashMap<String, (AddressSize, Span)>,
+    exports: Vec<(String, Span)>,
+    globals: HashMap<String, (AddressSize, Span)>,
+    /// Fragments started with `.org`, by name, in the order they were started.
+    orgs: Vec<(String, u16)>,
+    entry: Option<(String, Span)>,
+    inits: Vec<(String, Span)>,
+    lines: Vec<Line>,
+    /// The lint levels at the current element.
+    lints: Lints,
+    warnings: Vec<Warning>,
+    /// Every label named anywhere in the module.
+    references: HashSet<String>,
+    /// Labels that might not be used, with the level of [`Lint::UnusedLabel`] where they are
+    /// defined.
+    unused_candidates: Vec<(String, Level)>,
+    /// Labels addressed as absolute that could be on the zero page, with where they are used.
+    absolute_uses: Vec<(String, Span, Level)>,
+    cpu: Cpu,
+    checks: Vec<Check>,
+    /// The number of index values indexed reads use, inside a `.pagecheck` region.
+    page_check: Option<u16>,
+    /// The open `.cycles` blocks, innermost last.
+    cycle_blocks: Vec<CycleBlock>,
 }
 
 impl Default for GenerationState {
     fn default() -> Self {
         GenerationState {
-            program_counter: 0,
+            current_segment: DEFAULT_SEGMENT.to_owned(),
+            program_counters: HashMap::new(),
             label_locations: HashMap::new(),
+            label_spans: HashMap::new(),
+            imports: HashMap::new(),
+            exports: Vec::new(),
+            globals: HashMap::new(),
+            orgs: Vec::new(),
+            entry: None,
+            inits: Vec::new(),
+            lines: Vec::new(),
+            lints: Lints::default(),
+            warnings: Vec::new(),
+            references: HashSet::new(),
+            unused_candidates: Vec::new(),
+            absolute_uses: Vec::new(),
+            cpu: Cpu::default(),
+            checks: Vec::new(),
+            page_check: None,
+            cycle_blocks: Vec::new(),
         }
     }
 }
 
-// TODO the second time around the PC is still being incremented.
-// That's probably fine, since we don't use it to create new labels?
-pub fn generate_code(parsed: Parsed) -> Result<Vec<u8>, Error> {
+impl GenerationState {
+    fn program_counter(&mut self) -> &mut u16 {
+        self.program_counters
+            .entry(self.current_segment.clone())
+            .or_insert(0)
+    }
+
+    /// Symbols declared before use with a zero page size get zero page addressing.
+    fn is_zero_page(&self, label: &str) -> bool {
+        !self.label_locations.contains_key(label)
+            && [&self.imports, &self.globals]
+                .iter()
+                .any(|symbols| matches!(symbols.get(label), Some((AddressSize::ZeroPage, _))))
+    }
+
+    /// The name of the fragment at `address`, starting it if this is the first `.org` there.
+    fn start_org(&mut self, address: u16) -> String {
+        let name = format!("ORG_{:04X}", address);
+        if !self.orgs.iter().any(|(n, _)| *n == name) {
+            self.orgs.push((name.clone(), address));
+        }
+        name
+    }
+
+    /// The address of `segment` if it was started with `.org`.
+    fn org_address(&self, segment: &str) -> Option<u16> {
+        self.orgs
+            .iter()
+            .find(|(name, _)| name == segment)
+            .map(|(_, address)| *address)
+    }
+
+    /// Moves the program counter past `size` bytes of output from the element at `span`.
+    fn advance(&mut self, size: u16, span: Span) -> Result<(), Error> {
+        let base = self.org_address(&self.current_segment).unwrap_or(0);
+        let program_counter = self.program_counter();
+        match program_counter.checked_add(size) {
+            Some(end) if base as usize + end as usize <= 0x10000 => {
+                *program_counter = end;
+                Ok(())
+            }
+            _ => Err(Error::ProgramCounterOverflow(span)),
+        }
+    }
+
+    fn warn(&mut self, lint: Lint, level: Level, message: String, span: Span) {
+        if level != Level::Allow {
+            self.warnings.push(Warning {
+                lint,
+                level,
+                message,
+                span,
+                module: 0,
+            });
+        }
+    }
+
+    /// Leaves a check of the element at `span` starting at `offset` in the current segment for
+    /// the linker.
+    fn check(&mut self, kind: CheckKind, lint: Lint, offset: u16, span: Span) {
+        let level = self.lints.level(lint);
+        if level != Level::Allow {
+            self.checks.push(Check {
+                kind,
+                segment: self.current_segment.clone(),
+                offset,
+                source_start: span.start as u32,
+                source_end: span.end as u32,
+                deny: level == Level::Deny,
+            });
+        }
+    }
+
+    /// Records where the element starting at `source_offset` is placed.
+    fn add_line(&mut self, source_offset: usize, size: u16, cycles: Option<Cycles>) {
+        let line = Line {
+            source_offset: source_offset as u32,
+            segment: self.current_segment.clone(),
+            offset: *self.program_counter(),
+            size,
+            cycles,
+        };
+        self.lines.push(line);
+    }
+
+    /// Adds an instruction's cycles to the open `.cycles` blocks. A page crossing inside a
+    /// `.pagecheck` region where `page_cross` is denied fails the link, so it doesn't count towards
+    /// the worst case.
+    fn count_cycles(&mut self, cycles: Cycles) {
+        let denied = self.page_check.is_some() && self.lints.level(Lint::PageCross) == Level::Deny;
+        let page_cross = if denied { cycles.page_cross } else { 0 };
+        for block in &mut self.cycle_blocks {
+            block.best += cycles.best() as u32;
+            block.worst += (cycles.worst() - page_cross) as u32;
+        }
+    }
+
+    /// `span` is where the label is used, for errors.
+    fn resolve(
+        &self,
+        label: &str,
+        span: Span,
+        pass: Pass,
+    ) -> Result<Option<(RelocationTarget, u16)>, Error> {
+        match self.label_locations.get(label) {
+            Some((segment, offset)) => {
+                Ok(Some((RelocationTarget::Segment(segment.clone()), *offset)))
+            }
+            None if pass == Pass::First => Ok(None),
+            None if self.imports.contains_key(label) || self.globals.contains_key(label) => {
+                Ok(Some((RelocationTarget::Import(label.to_owned()), 0)))
+            }
+            None => Err(Error::UndefinedSymbol(label.to_owned(), span)),
+        }
+    }
+}
+
+/// Errors are collected rather than stopping at the first, and come out in source order, as do
+/// the warnings. `lints` are the levels at the start of the module.
+pub fn generate_code(
+    parsed: Parsed,
+    lints: &Lints,
+    cpu: Cpu,
+) -> Result<(Object, Vec<Warning>), Vec<Error>> {
     dbg!(&parsed);
-    let mut generation_state = GenerationState::default();
+    let mut generation_state = GenerationState {
+        lints: lints.clone(),
+        cpu,
+        ..GenerationState::default()
+    };
     let res = parsed
         .0
         .into_iter()
-        .map(|element| match element {
-            Element::Instruction(instruction) => {
-                emit_instruction(instruction, &mut generation_state)
-            }
-            Element::Label(l) => {
-                dbg!(&generation_state);
-                generation_state
-                    .label_locations
-                    .insert(l, generation_state.program_counter);
-                Ok(EmitResult::NoBytesRequired) // TODO pretty wasteful?
-            }
+        .map(|located| {
+            let Located {
+                span,
+                operand,
+                value,
+            } = located;
+            let offset = span.start;
+            let operand = operand.unwrap_or(span);
+            generation_state
+                .references
+                .extend(references(&value).into_iter().map(str::to_owned));
+            let emit_result = match value {
+                Element::Instruction(instruction) => {
+                    if !generation_state.cpu.supports(&instruction) {
+                        let mnemonic = instruction.mnemonic();
+                        return Err(Error::UnsupportedInstruction(mnemonic, cpu, span));
+                    }
+                    let instruction = match instruction.zero_page() {
+                        Some(zp) if generation_state.is_zero_page(zp.label().unwrap()) => zp,
+                        Some(zp) => {
+                            let level = generation_state.lints.level(Lint::ImplicitAbsolute);
+                            let label = zp.label().unwrap().to_owned();
+                            generation_state.absolute_uses.push((label, operand, level));
+                            instruction
+                        }
+                        None => instruction,
+                    };
+                    let pc = *generation_state.program_counter();
+                    if let (Cpu::Nmos6502, Instruction::JmpAbsoluteIndirect(_)) =
+                        (cpu, &instruction)
+                    {
+                        let (kind, lint) =
+                            (CheckKind::JmpIndirectPageWrap, Lint::JmpIndirectPageWrap);
+                        generation_state.check(kind, lint, pc, operand);
+                    }
+                    if let Some(indexes) = generation_state.page_check {
+                        let kind = match &instruction {
+                            Instruction::Relative(_, _) => Some(CheckKind::BranchPageCross),
+                            i if i.is_indexed_absolute() => {
+                                Some(CheckKind::IndexedPageCross { indexes })
+                            }
+                            _ => None,
+                        };
+                        if let Some(kind) = kind {
+                            generation_state.check(kind, Lint::PageCross, pc, operand);
+                        }
+                    }
+                    let cycles = cpu.cycles(&instruction);
+                    generation_state.count_cycles(cycles);
+                    generation_state.add_line(offset, instruction.size(), Some(cycles));
+                    generation_state.advance(instruction.size(), span)?;
+                    let segment = generation_state.current_segment.clone();
+                    emit_instruction(
+                        instruction,
+                        &segment,
+                        pc,
+                        operand,
+                        &generation_state,
+                        Pass::First,
+                    )
+                }
+                Element::Label(l) => {
+                    dbg!(&generation_state);
+                    let name_span = Span::new(span.start, span.start + l.len());
+                    if generation_state.label_locations.contains_key(&l) {
+                        return Err(Error::DuplicateLabel(l, name_span));
+                    }
+                    generation_state.add_line(offset, 0, None);
+                    let location = (
+                        generation_state.current_segment.clone(),
+                        *generation_state.program_counter(),
+                    );
+                    if Mnemonic::from_str(&l.to_ascii_uppercase()).is_ok() {
+                        let level = generation_state.lints.level(Lint::MnemonicLabel);
+                        let message = format!("label {} has the name of an instruction", l);
+                        generation_state.warn(Lint::MnemonicLabel, level, message, name_span);
+                    }
+                    let level = generation_state.lints.level(Lint::UnusedLabel);
+                    generation_state.unused_candidates.push((l.clone(), level));
+                    generation_state.label_spans.insert(l.clone(), name_span);
+                    generation_state.label_locations.insert(l, location);
+                    Ok(EmitResult::NoBytesRequired) // TODO pretty wasteful?
+                }
+                Element::Directive(Directive::Segment(name)) => {
+                    generation_state.current_segment = name;
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::Export(names)) => {
+                    generation_state
+                        .exports
+                        .extend(names.into_iter().map(|name| (name, operand)));
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::Import(names, size)) => {
+                    for name in names {
+                        generation_state.imports.insert(name, (size, operand));
+                    }
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::Global(names, size)) => {
+                    for name in names {
+                        generation_state.globals.insert(name, (size, operand));
+                    }
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::BasicStub(target)) => {
+                    let pc = *generation_state.program_counter();
+                    generation_state.add_line(offset, BASIC_STUB_SIZE, None);
+                    generation_state.advance(BASIC_STUB_SIZE, span)?;
+                    let segment = generation_state.current_segment.clone();
+                    emit_basic_stub(
+                        target,
+                        &segment,
+                        pc,
+                        operand,
+                        &generation_state,
+                        Pass::First,
+                    )
+                }
+                Element::Directive(Directive::Entry(name)) => {
+                    generation_state.entry = Some((name, operand));
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::Init(name)) => {
+                    generation_state.inits.push((name, operand));
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::Org(address)) => {
+                    let current = generation_state.org_address(&generation_state.current_segment);
+                    let pc = *generation_state.program_counter();
+                    if current.map(|org| org as usize + pc as usize) == Some(address as usize) {
+                        let level = generation_state.lints.level(Lint::RedundantOrg);
+                        let message = format!("the program counter is already at ${:04X}", address);
+                        generation_state.warn(Lint::RedundantOrg, level, message, operand);
+                    }
+                    generation_state.current_segment = generation_state.start_org(address);
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::PageCheck(indexes)) => {
+                    generation_state.page_check = Some(indexes);
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::EndPageCheck) => {
+                    generation_state.page_check = None;
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::Cycles { min, max }) => {
+                    generation_state.cycle_blocks.push(CycleBlock {
+                        min,
+                        max,
+                        best: 0,
+                        worst: 0,
+                        span: operand,
+                    });
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::EndCycles) => {
+                    match generation_state.cycle_blocks.pop() {
+                        Some(b) if b.best < b.min || b.worst > b.max => {
+                            Err(Error::CycleBudget(b.best, b.worst, b.min, b.max, b.span))
+                        }
+                        Some(_) => Ok(EmitResult::NoBytesRequired),
+                        None => Err(Error::UnmatchedCycles(span)),
+                    }
+                }
+                Element::Directive(Directive::Warning(level, lint)) => {
+                    match lint {
+                        Some(lint) => generation_state.lints.set(lint, level),
+                        None => generation_state.lints.set_all(level),
+                    }
+                    Ok(EmitResult::NoBytesRequired)
+                }
+                Element::Directive(Directive::Vectors { nmi, reset, irq }) => {
+                    let segment = generation_state.start_org(VECTORS);
+                    let previous =
+                        std::mem::replace(&mut generation_state.current_segment, segment.clone());
+                    generation_state.add_line(offset, VECTORS_SIZE, None);
+                    let advanced = generation_state.advance(VECTORS_SIZE, span);
+                    generation_state.current_segment = previous;
+                    advanced?;
+                    let labels = vec![nmi, reset, irq];
+                    return emit_vectors(labels, operand, &generation_state, Pass::First)
+                        .map(|er| (segment, operand, er));
+                }
+            };
+            emit_result.map(|er| (generation_state.current_segment.clone(), operand, er))
         })
-        .collect::<Result<Vec<_>, _>>()
-        .and_then(|v| fill_in_states(v.into_iter(), &mut generation_state))
-        .map(|ers| {
-            ers.into_iter()
-                .filter_map(|er| match er {
-                    EmitResult::FullyDetermined(bytes) => Some(bytes),
-                    EmitResult::PartiallyUnknown(_) => panic!("PartiallyUnknown"),
-                    EmitResult::NoBytesRequired => None,
-                })
-                .flatten()
-                .collect::<Vec<u8>>()
-        });
+        .collect::<Vec<_>>();
+    let mut errors = vec![];
+    let unclosed = std::mem::take(&mut generation_state.cycle_blocks);
+    errors.extend(unclosed.into_iter().map(|b| Error::UnmatchedCycles(b.span)));
+    let ers = collect_errors(res, &mut errors);
+    let ers = collect_errors(fill_in_states(ers, &generation_state), &mut errors);
+    let object = build_object(ers, &generation_state, &mut errors);
     dbg!(&generation_state);
-    res
+    check_unused(&mut generation_state);
+    check_absolute_uses(&mut generation_state);
+    if errors.is_empty() {
+        let mut warnings = generation_state.warnings;
+        warnings.sort_by_key(|w| w.span.start);
+        Ok((object, warnings))
+    } else {
+        errors.sort_by_key(|e| e.span().start);
+        Err(errors)
+    }
+}
+
+/// The labels an element refers to.
+fn references(element: &Element) -> Vec<&str> {
+    match element {
+        Element::Instruction(instruction) => instruction.label().into_iter().collect(),
+        Element::Directive(Directive::Export(names))
+        | Element::Directive(Directive::Global(names, _)) => {
+            names.iter().map(String::as_str).collect()
+        }
+        Element::Directive(Directive::Entry(name))
+        | Element::Directive(Directive::Init(name))
+        | Element::Directive(Directive::BasicStub(name)) => vec![name],
+        Element::Directive(Directive::Vectors { nmi, reset, irq }) => vec![nmi, reset, irq],
+        _ => vec![],
+    }
+}
+
+fn check_unused(generation_state: &mut GenerationState) {
+    let candidates = std::mem::take(&mut generation_state.unused_candidates);
+    for (label, level) in candidates {
+        if !generation_state.references.contains(&label) {
+            let span = generation_state.label_spans[&label];
+            let message = format!("label {} is never used", label);
+            generation_state.warn(Lint::UnusedLabel, level, message, span);
+        }
+    }
+}
+
+/// Labels defined in the `ZEROPAGE` segment or at a fixed address below `$0100`, but used with
+/// absolute addressing because they were not known to be on the zero page yet.
+fn check_absolute_uses(generation_state: &mut GenerationState) {
+    let uses = std::mem::take(&mut generation_state.absolute_uses);
+    for (label, span, level) in uses {
+        let on_zero_page = match generation_state.label_locations.get(&label) {
+            Some((segment, offset)) => {
+                segment == ZEROPAGE_SEGMENT
+                    || generation_state
+                        .org_address(segment)
+                        .is_some_and(|org| org as usize + (*offset as usize) < 0x100)
+            }
+            None => false,
+        };
+        if on_zero_page {
+            let message = format!(
+                "label {} is on the zero page but is addressed as absolute, declare it before use",
+                label
+            );
+            generation_state.warn(Lint::ImplicitAbsolute, level, message, span);
+        }
+    }
 }
 
-fn fill_in_states<I: Iterator<Item = EmitResult>>(
-    ers: I,
-    generation_state: &mut GenerationState,
-) -> Result<Vec<EmitResult>, Error> {
-    ers.map(|er| match er {
-        EmitResult::PartiallyUnknown(instruction) => {
-            emit_instruction(instruction, generation_state)
+const ZEROPAGE_SEGMENT: &str = "ZEROPAGE";
+
+/// The successful results, with the errors moved to `errors`.
+fn collect_errors<T>(results: Vec<Result<T, Error>>, errors: &mut Vec<Error>) -> Vec<T> {
+    results
+        .into_iter()
+        .filter_map(|result| result.map_err(|e| errors.push(e)).ok())
+        .collect()
+}
+
+/// Output with the segment it goes into and the source span of the operand.
+type Emitted = (String, Span, EmitResult);
+
+fn fill_in_states(
+    ers: Vec<Emitted>,
+    generation_state: &GenerationState,
+) -> Vec<Result<Emitted, Error>> {
+    ers.into_iter()
+        .map(|(segment, span, er)| {
+            let er = match er {
+                EmitResult::PartiallyUnknown(Pending::Instruction {
+                    instruction,
+                    offset,
+                }) => emit_instruction(
+                    instruction,
+                    &segment,
+                    offset,
+                    span,
+                    generation_state,
+                    Pass::Last,
+                )?,
+                EmitResult::PartiallyUnknown(Pending::BasicStub { target, offset }) => {
+                    emit_basic_stub(target, &segment, offset, span, generation_state, Pass::Last)?
+                }
+                EmitResult::PartiallyUnknown(Pending::Vectors(labels)) => {
+                    emit_vectors(labels, span, generation_state, Pass::Last)?
+                }
+                other => other,
+            };
+            Ok((segment, span, er))
+        })
+        .collect()
+}
+
+fn build_object(
+    ers: Vec<Emitted>,
+    generation_state: &GenerationState,
+    errors: &mut Vec<Error>,
+) -> Object {
+    let mut object = Object::default();
+    for (name, org) in &generation_state.orgs {
+        object.segments.push(ObjectSegment {
+            name: name.clone(),
+            org: Some(*org),
+            bytes: vec![],
+            relocations: vec![],
+        });
+    }
+    for (segment, _, er) in ers {
+        let (bytes, relocations) = match er {
+            EmitResult::FullyDetermined(bytes) => (bytes, vec![]),
+            EmitResult::Relocatable(bytes, relocations) => (bytes, relocations),
+            EmitResult::PartiallyUnknown(_) => unreachable!("Unknown after last pass"),
+            EmitResult::NoBytesRequired => continue,
+        };
+        let idx = match object.segments.iter().position(|s| s.name == segment) {
+            Some(idx) => idx,
+            None => {
+                object.segments.push(ObjectSegment {
+                    name: segment,
+                    org: None,
+                    bytes: vec![],
+                    relocations: vec![],
+                });
+                object.segments.len() - 1
+            }
+        };
+        let object_segment = &mut object.segments[idx];
+        for mut relocation in relocations {
+            relocation.offset += object_segment.bytes.len() as u16;
+            object_segment.relocations.push(relocation);
+        }
+        object_segment.bytes.extend(bytes);
+    }
+    object.symbols = generation_state
+        .label_locations
+        .iter()
+        .map(|(name, (segment, offset))| Symbol {
+            name: name.clone(),
+            segment: segment.clone(),
+            offset: *offset,
+            exported: generation_state.exports.iter().any(|(e, _)| e == name)
+                || generation_state.globals.contains_key(name),
+        })
+        .collect();
+    object.symbols.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for (name, span) in &generation_state.exports {
+        if !generation_state.label_locations.contains_key(name) {
+            errors.push(Error::UndefinedExport(name.clone(), *span));
         }
-        other => Ok(other),
-    })
-    .collect()
+    }
+    for name in generation_state.imports.keys() {
+        if let Some(span) = generation_state
+            .label_locations
+            .get(name)
+            .and(generation_state.label_spans.get(name))
+        {
+            errors.push(Error::ImportedSymbolDefined(name.clone(), *span));
+        }
+    }
+    object.imports = generation_state
+        .imports
+        .iter()
+        .chain(&generation_state.globals)
+        .filter(|(name, _)| !generation_state.label_locations.contains_key(*name))
+        .map(|(name, (size, span))| Import {
+            name: name.clone(),
+            size: *size,
+            source_start: span.start as u32,
+            source_end: span.end as u32,
+        })
+        .collect();
+    object.imports.sort_by(|a, b| a.name.cmp(&b.name));
+    if let Some((entry, span)) = &generation_state.entry {
+        if let Err(e) = generation_state.resolve(entry, *span, Pass::Last) {
+            errors.push(e);
+        }
+        object.entry = Some(entry.clone());
+    }
+    for (init, span) in &generation_state.inits {
+        if let Err(e) = generation_state.resolve(init, *span, Pass::Last) {
+            errors.push(e);
+        }
+    }
+    object.inits = generation_state
+        .inits
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect();
+    object.lines = generation_state.lines.clone();
+    object.checks = generation_state.checks.clone();
+    object
 }
 
+/// `offset` is where the instruction starts in `segment`.
 fn emit_instruction(
     instruction: Instruction,
-    generation_state: &mut GenerationState,
+    segment: &str,
+    offset: u16,
+    span: Span,
+    generation_state: &GenerationState,
+    pass: Pass,
 ) -> Result<EmitResult, Error> {
-    match &instruction {
-        Instruction::StzAbsolute(ot) => {
-            let GenerationState {
-                program_counter,
-                label_locations,
-            } = generation_state;
-            let instruction_byte = instruction.instruction_byte();
-            match ot {
-                OperandExpression::Known(addr) => {
-                    known_16bit(instruction_byte, program_counter, *addr)
-                }
-                OperandExpression::Label(l) => match label_locations.get(l) {
-                    Some(addr) => known_16bit(instruction_byte, program_counter, *addr),
-                    None => {
-                        increment_pc(program_counter, 3);
-                        Ok(EmitResult::PartiallyUnknown(instruction))
-                    }
-                },
+    let instruction_byte = instruction.instruction_byte();
+    let (label, kind) = match &instruction {
+        Instruction::StzAbsolute(ot)
+        | Instruction::JmpAbsolute(ot)
+        | Instruction::JmpAbsoluteIndirect(ot)
+        | Instruction::LdaAbsoluteX(ot)
+        | Instruction::LdaAbsoluteY(ot) => match ot {
+            OperandExpression::Known(addr) => return known_16bit(instruction_byte, *addr),
+            OperandExpression::Label(l) => (l, RelocationKind::Word),
+            OperandExpression::LowByte(_) | OperandExpression::HighByte(_) => {
+                unreachable!("Byte selections are always 8-bit operands")
             }
+        },
+        Instruction::StzZeroPage(ot)
+        | Instruction::LdaImmediate(ot)
+        | Instruction::LdaZeroPageX(ot) => match ot {
+            OperandExpression::Known(val) => return known_8bit(instruction_byte, *val),
+            OperandExpression::Label(l) => (l, RelocationKind::ZeroPage),
+            OperandExpression::LowByte(l) => (l, RelocationKind::Low),
+            OperandExpression::HighByte(l) => (l, RelocationKind::High),
+        },
+        Instruction::RtsStack => return Ok(EmitResult::FullyDetermined(vec![instruction_byte])),
+        Instruction::Relative(_, target) => {
+            return emit_branch(
+                &instruction,
+                target,
+                segment,
+                offset,
+                span,
+                generation_state,
+                pass,
+            )
         }
-        Instruction::JmpAbsolute(ot) => {
-            let GenerationState {
-                program_counter,
-                label_locations,
-            } = generation_state;
-            let instruction_byte = instruction.instruction_byte();
-            match ot {
-                OperandExpression::Known(addr) => {
-                    known_16bit(instruction_byte, program_counter, *addr)
-                }
-                OperandExpression::Label(l) => match label_locations.get(l) {
-                    Some(addr) => known_16bit(instruction_byte, program_counter, *addr),
-                    None => {
-                        increment_pc(program_counter, 3);
-                        Ok(EmitResult::PartiallyUnknown(instruction))
+    };
+    match generation_state.resolve(label, span, pass)? {
+        Some((RelocationTarget::Segment(s), addend)) if kind == RelocationKind::ZeroPage => {
+            match generation_state
+                .org_address(&s)
+                .map(|a| a.wrapping_add(addend))
+            {
+                Some(value) if value > 0xFF => {
+                    Err(Error::OperandOutOfRange(label.clone(), value, span))
+                }
+                _ => relocatable(
+                    instruction_byte,
+                    kind,
+                    RelocationTarget::Segment(s),
+                    addend,
+                    span,
+                ),
+            }
+        }
+        Some((target, addend)) => relocatable(instruction_byte, kind, target, addend, span),
+        None => Ok(EmitResult::PartiallyUnknown(Pending::Instruction {
+            instruction,
+            offset,
+        })),
+    }
+}
+
+/// Branches within a fragment, or between fragments with fixed addresses, are resolved here.
+/// Others are left to the linker.
+fn emit_branch(
+    instruction: &Instruction,
+    target: &OperandExpression<u16>,
+    segment: &str,
+    offset: u16,
+    span: Span,
+    generation_state: &GenerationState,
+    pass: Pass,
+) -> Result<EmitResult, Error> {
+    let instruction_byte = instruction.instruction_byte();
+    let next = offset as i32 + 2;
+    let org = generation_state.org_address(segment);
+    let (name, distance) = match target {
+        OperandExpression::Known(address) => match org {
+            Some(org) => (
+                format!("${:04X}", address),
+                *address as i32 - org as i32 - next,
+            ),
+            None => return Err(Error::AbsoluteBranch(*address, span)),
+        },
+        OperandExpression::Label(l) => match generation_state.resolve(l, span, pass)? {
+            Some((RelocationTarget::Segment(s), addend)) if s == segment => {
+                (l.clone(), addend as i32 - next)
+            }
+            Some((RelocationTarget::Segment(s), addend)) => {
+                match (org, generation_state.org_address(&s)) {
+                    (Some(org), Some(address)) => (
+                        l.clone(),
+                        address as i32 + addend as i32 - org as i32 - next,
+                    ),
+                    _ => {
+                        return relocatable(
+                            instruction_byte,
+                            RelocationKind::Relative,
+                            RelocationTarget::Segment(s),
+                            addend,
+                            span,
+                        )
                     }
-                },
+                }
             }
+            Some((target, addend)) => {
+                return relocatable(
+                    instruction_byte,
+                    RelocationKind::Relative,
+                    target,
+                    addend,
+                    span,
+                )
+            }
+            None => {
+                return Ok(EmitResult::PartiallyUnknown(Pending::Instruction {
+                    instruction: instruction.clone(),
+                    offset,
+                }))
+            }
+        },
+        OperandExpression::LowByte(_) | OperandExpression::HighByte(_) => {
+            unreachable!("Byte selections are always 8-bit operands")
         }
-        Instruction::RtsStack => Ok(EmitResult::FullyDetermined(vec![0x60])),
+    };
+    match i8::try_from(distance) {
+        Ok(distance) => known_8bit(instruction_byte, distance as u8),
+        Err(_) => Err(Error::BranchOutOfRange(name, distance, span)),
     }
 }
 
-fn increment_pc(program_counter: &mut u16, by: u16) {
-    *program_counter = program_counter
-        .checked_add(by)
-        .expect("Program counter overflow")
+const BASIC_STUB_SIZE: u16 = 13;
+const BASIC_SYS_TOKEN: u8 = 0x9E;
+const BASIC_STUB_LINE: u16 = 10;
+
+/// `10 SYS target` followed by the end of program marker, in the tokenized form of Commodore
+/// BASIC 2.0.
+fn emit_basic_stub(
+    target: String,
+    segment: &str,
+    offset: u16,
+    span: Span,
+    generation_state: &GenerationState,
+    pass: Pass,
+) -> Result<EmitResult, Error> {
+    let (target, addend) = match generation_state.resolve(&target, span, pass)? {
+        Some(resolved) => resolved,
+        None => {
+            return Ok(EmitResult::PartiallyUnknown(Pending::BasicStub {
+                target,
+                offset,
+            }))
+        }
+    };
+    let mut bytes = vec![0, 0];
+    bytes.extend(&BASIC_STUB_LINE.to_le_bytes());
+    bytes.push(BASIC_SYS_TOKEN);
+    bytes.extend(&[0; 5]);
+    bytes.extend(&[0, 0, 0]);
+    let relocations = vec![
+        Relocation {
+            offset: 0,
+            kind: RelocationKind::Word,
+            target: RelocationTarget::Segment(segment.to_owned()),
+            addend: offset + BASIC_STUB_SIZE - 2,
+            source_start: span.start as u32,
+            source_end: span.end as u32,
+        },
+        Relocation {
+            offset: 5,
+            kind: RelocationKind::Decimal,
+            target,
+            addend,
+            source_start: span.start as u32,
+            source_end: span.end as u32,
+        },
+    ];
+    Ok(EmitResult::Relocatable(bytes, relocations))
 }
 
-fn known_16bit(
-    instruction_byte: u8,
-    program_counter: &mut u16,
-    val: u16,
+const VECTORS: u16 = 0xFFFA;
+const VECTORS_SIZE: u16 = 6;
+
+/// The addresses of the NMI, RESET and IRQ handlers.
+fn emit_vectors(
+    labels: Vec<String>,
+    span: Span,
+    generation_state: &GenerationState,
+    pass: Pass,
 ) -> Result<EmitResult, Error> {
+    let mut relocations = vec![];
+    for (idx, label) in labels.iter().enumerate() {
+        match generation_state.resolve(label, span, pass)? {
+            Some((target, addend)) => relocations.push(Relocation {
+                offset: 2 * idx as u16,
+                kind: RelocationKind::Word,
+                target,
+                addend,
+                source_start: span.start as u32,
+                source_end: span.end as u32,
+            }),
+            None => return Ok(EmitResult::PartiallyUnknown(Pending::Vectors(labels))),
+        }
+    }
+    Ok(EmitResult::Relocatable(
+        vec![0; VECTORS_SIZE as usize],
+        relocations,
+    ))
+}
+
+fn known_16bit(instruction_byte: u8, val: u16) -> Result<EmitResult, Error> {
     let [l, h] = val.to_le_bytes();
     let bytes = vec![instruction_byte, l, h];
-    increment_pc(program_counter, 3);
     Ok(EmitResult::FullyDetermined(bytes))
 }
 
-// fn known_8bit(instruction_byte: u8, program_counter: &mut u16, val: u8) -> Result<EmitResult, Error> {
-//     let bytes = vec![instruction_byte, val];
-//     increment_pc(program_counter, 2);
-//     Ok(EmitResult::FullyDetermined(bytes))
-// }
+fn known_8bit(instruction_byte: u8, val: u8) -> Result<EmitResult, Error> {
+    let bytes = vec![instruction_byte, val];
+    Ok(EmitResult::FullyDetermined(bytes))
+}
+
+fn relocatable(
+    instruction_byte: u8,
+    kind: RelocationKind,
+    target: RelocationTarget,
+    addend: u16,
+    span: Span,
+) -> Result<EmitResult, Error> {
+    let mut bytes = vec![instruction_byte];
+    bytes.resize(1 + kind.size() as usize, 0);
+    let relocation = Relocation {
+        offset: 1,
+        kind,
+        target,
+        addend,
+        source_start: span.start as u32,
+        source_end: span.end as u32,
+    };
+    Ok(EmitResult::Relocatable(bytes, vec![relocation]))
+}
@@ -0,0 +1,98 @@
+//! The processors code can be assembled for.
+
+use crate::parser::{Instruction, Mnemonic};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default, strum_macros::Display)]
+pub enum Cpu {
+    /// The original NMOS 6502.
+    #[strum(serialize = "6502")]
+    Nmos6502,
+    /// The CMOS 65C02, with `STZ`, `BRA` and the indirect `JMP` fixed.
+    #[default]
+    #[strum(serialize = "65C02")]
+    Cmos65C02,
+}
+
+impl Cpu {
+    pub(crate) fn supports(self, instruction: &Instruction) -> bool {
+        match self {
+            Cpu::Cmos65C02 => true,
+            Cpu::Nmos6502 => !matches!(instruction.mnemonic(), Mnemonic::STZ | Mnemonic::BRA),
+        }
+    }
+
+    /// The cycles the instruction takes on this processor.
+    pub(crate) fn cycles(self, instruction: &Instruction) -> Cycles {
+        use Instruction::*;
+        match instruction {
+            StzAbsolute(_) => Cycles::new(4),
+            StzZeroPage(_) => Cycles::new(3),
+            RtsStack => Cycles::new(6),
+            JmpAbsolute(_) => Cycles::new(3),
+            JmpAbsoluteIndirect(_) => match self {
+                Cpu::Nmos6502 => Cycles::new(5),
+                Cpu::Cmos65C02 => Cycles::new(6),
+            },
+            LdaImmediate(_) => Cycles::new(2),
+            LdaZeroPageX(_) => Cycles::new(4),
+            LdaAbsoluteX(_) | LdaAbsoluteY(_) => Cycles {
+                page_cross: 1,
+                ..Cycles::new(4)
+            },
+            Relative(Mnemonic::BRA, _) => Cycles {
+                page_cross: 1,
+                ..Cycles::new(3)
+            },
+            Relative(..) => Cycles {
+                page_cross: 1,
+                taken: 1,
+                ..Cycles::new(2)
+            },
+        }
+    }
+}
+
+/// The cycles an instruction takes: `base`, plus `taken` when it is a branch that is taken, plus
+/// `page_cross` when its indexed read or taken branch crosses a page.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct Cycles {
+    pub base: u8,
+    pub page_cross: u8,
+    pub taken: u8,
+}
+
+impl Cycles {
+    pub fn new(base: u8) -> Self {
+        Cycles {
+            base,
+            ..Cycles::default()
+        }
+    }
+
+    pub fn best(self) -> u8 {
+        self.base
+    }
+
+    pub fn worst(self) -> u8 {
+        self.base + self.taken + self.page_cross
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::OperandExpression;
+
+    #[test]
+    fn cycles() {
+        let target = OperandExpression::Known(0x1234);
+        let jmp = Instruction::JmpAbsoluteIndirect(target.clone());
+        assert_eq!(5, Cpu::Nmos6502.cycles(&jmp).worst());
+        assert_eq!(6, Cpu::Cmos65C02.cycles(&jmp).worst());
+
+        let beq = Cpu::Cmos65C02.cycles(&Instruction::Relative(Mnemonic::BEQ, target.clone()));
+        assert_eq!((2, 4), (beq.best(), beq.worst()));
+        let bra = Cpu::Cmos65C02.cycles(&Instruction::Relative(Mnemonic::BRA, target));
+        assert_eq!((3, 4), (bra.best(), bra.worst()))
+    }
+}
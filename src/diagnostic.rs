@@ -0,0 +1,182 @@
+//! Source locations for errors, and rendering them with the offending source line.
+
+use std::fmt::Write;
+
+/// A byte range in the source of a module.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, strum_macros::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub severity: Severity,
+    /// The index of the module `span` is in, among the linked modules. Always 0 for errors found
+    /// while assembling a single module.
+    pub module: usize,
+}
+
+/// Renders the diagnostic with the source file of its module, `files` being the sources of the
+/// linked modules in order. Without a source file, only the message is rendered.
+pub fn render(files: &[SourceFile<'_>], diagnostic: &Diagnostic) -> String {
+    match files.get(diagnostic.module) {
+        Some(file) => file.render(diagnostic),
+        None => format!("{}: {}\n", diagnostic.severity, diagnostic.message),
+    }
+}
+
+/// Line and column of a byte offset, both starting at 1. Columns count characters.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A named source text that spans point into.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct SourceFile<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn new(name: &'a str, text: &'a str) -> Self {
+        SourceFile { name, text }
+    }
+
+    pub fn location(&self, offset: usize) -> Location {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Location {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    /// The message, the location and the source line with the span underlined:
+    ///
+    /// ```text
+    /// error: symbol loop2 is not defined or imported
+    ///  --> main.s:3:7
+    ///   |
+    /// 3 |   JMP loop2
+    ///   |       ^^^^^
+    /// ```
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let span = diagnostic.span;
+        let location = self.location(span.start);
+        let start = span.start.min(self.text.len());
+        let line_start = self.text[..start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = self.text[start..]
+            .find('\n')
+            .map_or(self.text.len(), |idx| start + idx);
+        let line = &self.text[line_start..line_end];
+        let end = span.end.clamp(start, line_end);
+        let carets = std::cmp::max(1, self.text[start..end].chars().count());
+
+        let gutter = " ".repeat(location.line.to_string().len());
+        let mut out = String::new();
+        writeln!(out, "{}: {}", diagnostic.severity, diagnostic.message).unwrap();
+        writeln!(
+            out,
+            "{}--> {}:{}:{}",
+            gutter, self.name, location.line, location.column
+        )
+        .unwrap();
+        writeln!(out, "{} |", gutter).unwrap();
+        writeln!(out, "{} | {}", location.line, line).unwrap();
+        writeln!(
+            out,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(location.column - 1),
+            "^".repeat(carets)
+        )
+        .unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location() {
+        let source = SourceFile::new("main.s", "  RTS\nloop:\n  JMP loop\n");
+        assert_eq!(Location { line: 1, column: 1 }, source.location(0));
+        assert_eq!(Location { line: 3, column: 7 }, source.location(18));
+        assert_eq!(Location { line: 4, column: 1 }, source.location(23))
+    }
+
+    #[test]
+    fn render() {
+        let source = SourceFile::new("main.s", "  RTS\nloop:\n  JMP loop2\n");
+        let diagnostic = Diagnostic {
+            message: "symbol loop2 is not defined or imported".to_owned(),
+            span: Span::new(18, 23),
+            severity: Severity::Error,
+            module: 0,
+        };
+        let expected = "error: symbol loop2 is not defined or imported
+ --> main.s:3:7
+  |
+3 |   JMP loop2
+  |       ^^^^^
+";
+        assert_eq!(expected, source.render(&diagnostic))
+    }
+
+    #[test]
+    fn render_module() {
+        let files = [
+            SourceFile::new("main.s", "  JSR print\n"),
+            SourceFile::new("print.s", "  .import putc\n  JMP putc\n"),
+        ];
+        let diagnostic = Diagnostic {
+            message: "symbol putc is imported but not exported by any module".to_owned(),
+            span: Span::new(21, 25),
+            severity: Severity::Error,
+            module: 1,
+        };
+        assert!(super::render(&files, &diagnostic).contains(" --> print.s:2:7\n"));
+        let diagnostic = Diagnostic {
+            module: 2,
+            ..diagnostic
+        };
+        assert_eq!(
+            "error: symbol putc is imported but not exported by any module\n",
+            super::render(&files, &diagnostic)
+        )
+    }
+
+    #[test]
+    fn render_end_of_file() {
+        let source = SourceFile::new("main.s", "  RTS");
+        let diagnostic = Diagnostic {
+            message: "expected a newline".to_owned(),
+            span: Span::new(5, 5),
+            severity: Severity::Warning,
+            module: 0,
+        };
+        let rendered = source.render(&diagnostic);
+        assert!(rendered.starts_with("warning: expected a newline\n"));
+        assert!(rendered.ends_with("1 |   RTS\n  |      ^\n"))
+    }
+}
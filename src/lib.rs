@@ -1,20 +1,162 @@
+use cpu::Cpu;
+use diagnostic::{Diagnostic, Severity};
+use linker::{Layout, LinkerConfig, Segment};
+use lint::{Level, Lints, Warning};
+use object::Object;
+
+pub mod archive;
 mod code_generator;
+pub mod cpu;
+pub mod diagnostic;
+pub mod linker;
+pub mod lint;
+pub mod listing;
+pub mod object;
+pub mod output;
 mod parser;
+pub mod symbols;
 
 #[derive(Debug)]
 pub enum Error<'a> {
     ParsingError(parser::Error<&'a str>),
     CodeGenError(code_generator::Error),
+    LinkError(linker::Error),
+    /// A warning whose lint is set to [`Level::Deny`].
+    DeniedWarning(Warning),
+}
+
+impl<'a> Error<'a> {
+    /// The error with its location in `source`, the text the module was assembled from. Linker
+    /// errors that are not about one place in the source, like segment overflows, have none.
+    pub fn diagnostic(&self, source: &str) -> Option<Diagnostic> {
+        match self {
+            Error::ParsingError(e) => Some(e.diagnostic(source)),
+            Error::CodeGenError(e) => Some(e.diagnostic()),
+            Error::DeniedWarning(w) => Some(w.diagnostic()),
+            Error::LinkError(e) => e.location().map(|location| Diagnostic {
+                message: e.to_string(),
+                span: location.span,
+                severity: Severity::Error,
+                module: location.module,
+            }),
+        }
+    }
+}
+
+/// How an assembly reports errors and warnings.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    /// Assembly stops after this many errors. Zero means no limit.
+    pub max_errors: usize,
+    /// The lint levels at the start of the module. `.warning` changes them from there on.
+    pub lints: Lints,
+    pub cpu: Cpu,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_errors: 20,
+            lints: Lints::default(),
+            cpu: Cpu::default(),
+        }
+    }
+}
+
+/// Assembles into a single `CODE` segment starting at `$0000`.
+pub fn assemble(i: &str) -> Result<Vec<u8>, Error<'_>> {
+    assemble_with_config(i, &LinkerConfig::default())
+        .map(|segments| segments.into_iter().flat_map(|s| s.bytes).collect())
 }
 
-pub fn assemble(i: &str) -> Result<Vec<u8>, Error> {
-    let parsed = parser::parse(i).map_err(Error::ParsingError)?;
-    code_generator::generate_code(parsed).map_err(Error::CodeGenError)
+/// Assembles a single module and places every segment as described by `config`.
+pub fn assemble_with_config<'a>(
+    i: &'a str,
+    config: &LinkerConfig,
+) -> Result<Vec<Segment>, Error<'a>> {
+    let object = assemble_object(i)?;
+    linker::link(config, &[object]).map_err(Error::LinkError)
+}
+
+/// Like [`assemble_with_config`], but keeps the resolved symbols, relocations and source lines
+/// for symbol files and listings.
+pub fn assemble_layout<'a>(i: &'a str, config: &LinkerConfig) -> Result<Layout, Error<'a>> {
+    let object = assemble_object(i)?;
+    let layout = linker::layout(config, &[object], &[]).map_err(Error::LinkError)?;
+    linker::denied_checks(&layout).map_err(Error::LinkError)?;
+    Ok(layout)
+}
+
+/// Like [`assemble_layout`], with the options of [`assemble_object_with_options`]. The warnings
+/// include the checks that can only be made at the final addresses.
+pub fn assemble_layout_with_options<'a>(
+    i: &'a str,
+    config: &LinkerConfig,
+    options: &Options,
+) -> Result<(Layout, Vec<Warning>), Vec<Error<'a>>> {
+    let (object, mut warnings) = assemble_object_with_options(i, options)?;
+    let layout = linker::layout(config, &[object], &[]).map_err(|e| vec![Error::LinkError(e)])?;
+    warnings.extend(layout.failed_checks.iter().map(Warning::from_failed_check));
+    warnings.sort_by_key(|w| w.span.start);
+    let denied: Vec<_> = warnings
+        .iter()
+        .filter(|w| w.level == Level::Deny)
+        .cloned()
+        .map(Error::DeniedWarning)
+        .collect();
+    if denied.is_empty() {
+        Ok((layout, warnings))
+    } else {
+        Err(denied)
+    }
+}
+
+/// Assembles a single module into a relocatable object, to be combined with others by
+/// [`linker::link`].
+pub fn assemble_object(i: &str) -> Result<Object, Error<'_>> {
+    let options = Options {
+        max_errors: 1,
+        ..Options::default()
+    };
+    assemble_object_with_options(i, &options)
+        .map(|(object, _)| object)
+        .map_err(|errors| errors.into_iter().next().unwrap())
+}
+
+/// Like [`assemble_object`], but carries on past errors. A line that does not parse is skipped, and
+/// every error up to `max_errors` is returned in source order. Warnings come with the object.
+pub fn assemble_object_with_options<'a>(
+    i: &'a str,
+    options: &Options,
+) -> Result<(Object, Vec<Warning>), Vec<Error<'a>>> {
+    let (parsed, parse_errors) = parser::parse(i, options.max_errors);
+    let mut errors: Vec<_> = parse_errors.into_iter().map(Error::ParsingError).collect();
+    if options.max_errors == 0 || errors.len() < options.max_errors {
+        match code_generator::generate_code(parsed, &options.lints, options.cpu) {
+            Ok((object, warnings)) => {
+                let (denied, warnings): (Vec<_>, Vec<_>) =
+                    warnings.into_iter().partition(|w| w.level == Level::Deny);
+                errors.extend(denied.into_iter().map(Error::DeniedWarning));
+                if errors.is_empty() {
+                    return Ok((object, warnings));
+                }
+            }
+            Err(e) => errors.extend(e.into_iter().map(Error::CodeGenError)),
+        }
+    }
+    errors.sort_by_key(|e| e.diagnostic(i).map(|d| d.span.start));
+    if options.max_errors != 0 {
+        errors.truncate(options.max_errors);
+    }
+    Err(errors)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diagnostic::{SourceFile, Span};
+    use crate::lint::Lint;
+    use crate::object::CheckKind;
 
     #[test]
     fn basic_assemble() {
@@ -46,4 +188,710 @@ mod tests {
             result.unwrap()
         )
     }
+
+    #[test]
+    fn rts_advances_pc() {
+        let input = "  RTS\nlbl:\n  JMP lbl\n";
+        let result = assemble(input);
+        assert_eq!(vec![0x60, 0x4C, 0x01, 0x00], result.unwrap())
+    }
+
+    fn segment_config() -> LinkerConfig {
+        "ZEROPAGE: start = $0000, size = $0100;\nCODE: start = $8000, size = $000A;\nRODATA: start = $C000, size = $1000;\n"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn segments_assemble() {
+        let input = "  JMP data\n  .segment \"RODATA\"\ndata:\n  JMP code\n  .segment \"CODE\"\ncode:\n  RTS\n";
+        let result = assemble_with_config(input, &segment_config()).unwrap();
+        assert_eq!(
+            vec![
+                Segment {
+                    name: "ZEROPAGE".to_owned(),
+                    start: 0x0000,
+                    bytes: vec![]
+                },
+                Segment {
+                    name: "CODE".to_owned(),
+                    start: 0x8000,
+                    bytes: vec![0x4C, 0x00, 0xC0, 0x60]
+                },
+                Segment {
+                    name: "RODATA".to_owned(),
+                    start: 0xC000,
+                    bytes: vec![0x4C, 0x03, 0x80]
+                },
+            ],
+            result
+        )
+    }
+
+    #[test]
+    fn unknown_segment() {
+        let input = "  .segment \"BSS\"\n  RTS\n";
+        let result = assemble_with_config(input, &segment_config());
+        assert!(matches!(
+            result,
+            Err(Error::LinkError(linker::Error::UnknownSegment(s))) if s == "BSS"
+        ))
+    }
+
+    #[test]
+    fn segment_overflow() {
+        let input = "  JMP $1234\n  JMP $1234\n  JMP $1234\n  RTS\n  RTS\n";
+        let result = assemble_with_config(input, &segment_config());
+        assert!(matches!(
+            result,
+            Err(Error::LinkError(linker::Error::SegmentOverflow {
+                needed: 11,
+                ..
+            }))
+        ))
+    }
+
+    #[test]
+    fn modules_link() {
+        let main = assemble_object("  .import print\n  JMP print\nloop:\n  JMP loop\n").unwrap();
+        let print = assemble_object("  .export print\nprint:\n  STZ $0300\n  RTS\n").unwrap();
+        let print = Object::from_bytes(&print.to_bytes().unwrap()).unwrap();
+        let result = linker::link(&segment_config(), &[main, print]).unwrap();
+        assert_eq!(
+            vec![0x4C, 0x06, 0x80, 0x4C, 0x03, 0x80, 0x9C, 0x00, 0x03, 0x60],
+            result[1].bytes
+        )
+    }
+
+    #[test]
+    fn globals_link() {
+        let main = assemble_object("  .global print, start\nstart:\n  JMP print\n").unwrap();
+        let print = assemble_object("  .global print, start\nprint:\n  JMP start\n").unwrap();
+        let result = linker::link(&segment_config(), &[main, print]).unwrap();
+        assert_eq!(vec![0x4C, 0x03, 0x80, 0x4C, 0x00, 0x80], result[1].bytes)
+    }
+
+    #[test]
+    fn zero_page_import() {
+        let main =
+            assemble_object("  .importzp ptr\n  .import far\n  STZ ptr\n  STZ far\n").unwrap();
+        let vars = assemble_object(
+            "  .export ptr, far\n  .segment \"ZEROPAGE\"\n  STZ $00\nptr:\n  .segment \"RODATA\"\nfar:\n",
+        )
+        .unwrap();
+        let result = linker::link(&segment_config(), &[main, vars]).unwrap();
+        assert_eq!(vec![0x64, 0x00], result[0].bytes);
+        assert_eq!(vec![0x64, 0x02, 0x9C, 0x00, 0xC0], result[1].bytes)
+    }
+
+    #[test]
+    fn undefined_symbol() {
+        let result = assemble_object("  JMP nowhere\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::UndefinedSymbol(s, span)))
+                if s == "nowhere" && span == Span::new(6, 13)
+        ))
+    }
+
+    #[test]
+    fn undefined_symbol_diagnostic() {
+        let input = "  RTS\nloop:\n  JMP loop2\n";
+        let diagnostic = assemble_object(input)
+            .unwrap_err()
+            .diagnostic(input)
+            .unwrap();
+        let expected = "error: symbol loop2 is not defined or imported
+ --> main.s:3:7
+  |
+3 |   JMP loop2
+  |       ^^^^^
+";
+        assert_eq!(
+            expected,
+            SourceFile::new("main.s", input).render(&diagnostic)
+        )
+    }
+
+    #[test]
+    fn parse_error_diagnostic() {
+        let input = "  RTS\n  JMP #$12\n";
+        let diagnostic = assemble_object(input)
+            .unwrap_err()
+            .diagnostic(input)
+            .unwrap();
+        assert_eq!(Span::new(8, 16), diagnostic.span)
+    }
+
+    #[test]
+    fn imported_symbol_defined() {
+        let result = assemble_object("  .import print\nprint:\n  RTS\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::ImportedSymbolDefined(s, span)))
+                if s == "print" && span == Span::new(16, 21)
+        ))
+    }
+
+    #[test]
+    fn undefined_export() {
+        let result = assemble_object("  .export nowhere\n  RTS\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::UndefinedExport(s, span)))
+                if s == "nowhere" && span == Span::new(10, 17)
+        ))
+    }
+
+    #[test]
+    fn unresolved_import() {
+        let main = assemble_object("  .import print\n  RTS\n").unwrap();
+        let result = linker::link(&segment_config(), &[main]);
+        let location = linker::Location {
+            module: 0,
+            span: Span::new(10, 15),
+        };
+        assert_eq!(
+            Err(linker::Error::UnresolvedImport(
+                "print".to_owned(),
+                Some(location)
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn link_error_diagnostic() {
+        let input = "  .import print\n  JMP print\n";
+        let error = assemble(input).unwrap_err();
+        let diagnostic = error.diagnostic(input).unwrap();
+        assert_eq!(Span::new(22, 27), diagnostic.span);
+        let expected = "error: symbol print is imported but not exported by any module
+ --> main.s:2:7
+  |
+2 |   JMP print
+  |       ^^^^^
+";
+        let files = [SourceFile::new("main.s", input)];
+        assert_eq!(expected, diagnostic::render(&files, &diagnostic));
+
+        let error = assemble_with_config("  RTS\n  .org $8000\n  RTS\n", &segment_config());
+        assert!(matches!(
+            error,
+            Err(Error::LinkError(linker::Error::Overlap(_, _, Some(location))))
+                if location.span == Span::new(19, 19)
+        ));
+        let error = assemble("  .segment \"BSS\"\n  RTS\n").unwrap_err();
+        assert_eq!(None, error.diagnostic(""))
+    }
+
+    #[test]
+    fn multiple_errors() {
+        let input = "  JMP nowhere\n  STA $12\n  .export missing\n  RTS\n";
+        let errors = assemble_object_with_options(input, &Options::default()).unwrap_err();
+        let spans: Vec<_> = errors
+            .iter()
+            .map(|e| e.diagnostic(input).unwrap().span)
+            .collect();
+        assert_eq!(
+            vec![Span::new(6, 13), Span::new(16, 19), Span::new(34, 41)],
+            spans
+        )
+    }
+
+    #[test]
+    fn max_errors() {
+        let input = "  JMP a\n  JMP b\n  JMP c\n";
+        let options = Options {
+            max_errors: 2,
+            ..Options::default()
+        };
+        assert_eq!(
+            2,
+            assemble_object_with_options(input, &options)
+                .unwrap_err()
+                .len()
+        );
+        let options = Options {
+            max_errors: 0,
+            ..Options::default()
+        };
+        assert_eq!(
+            3,
+            assemble_object_with_options(input, &options)
+                .unwrap_err()
+                .len()
+        )
+    }
+
+    #[test]
+    fn duplicate_label() {
+        let result = assemble_object("loop:\n  RTS\nloop:\n  RTS\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::DuplicateLabel(s, span)))
+                if s == "loop" && span == Span::new(12, 16)
+        ))
+    }
+
+    #[test]
+    fn program_counter_overflow() {
+        let result = assemble_object("  .org $FFFE\n  RTS\n  RTS\n  RTS\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::ProgramCounterOverflow(span)))
+                if span == Span::new(25, 30)
+        ))
+    }
+
+    #[test]
+    fn operand_out_of_range() {
+        let result = assemble_object("  .globalzp ptr\n  STZ ptr\n  .org $0300\nptr:\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::OperandOutOfRange(s, 0x0300, _)))
+                if s == "ptr"
+        ))
+    }
+
+    #[test]
+    fn branch_assemble() {
+        let input = "loop:\n  BNE done\n  BRA loop\ndone:\n  RTS\n  .org $0300\n  BEQ $0300\n";
+        let result = assemble_with_config(input, &segment_config()).unwrap();
+        assert_eq!(vec![0xD0, 0x02, 0x80, 0xFC, 0x60], result[1].bytes);
+        assert_eq!(vec![0xF0, 0xFE], result[3].bytes)
+    }
+
+    #[test]
+    fn branch_between_segments() {
+        let input = "  BCC data\n  .segment \"RODATA\"\ndata:\n";
+        let result = assemble_with_config(input, &segment_config());
+        assert!(matches!(
+            result,
+            Err(Error::LinkError(linker::Error::BranchOutOfRange {
+                distance: 0x3FFE,
+                ..
+            }))
+        ));
+        let config = "CODE: start = $8000, size = $10;\nRODATA: start = $8010, size = $10;"
+            .parse()
+            .unwrap();
+        let result = assemble_with_config(input, &config).unwrap();
+        assert_eq!(vec![0x90, 0x0E], result[0].bytes)
+    }
+
+    #[test]
+    fn branch_out_of_range() {
+        let mut input = "loop:\n".to_owned();
+        input.push_str(&"  JMP loop\n".repeat(43));
+        input.push_str("  BNE loop\n");
+        let result = assemble_object(&input);
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::BranchOutOfRange(s, -131, _)))
+                if s == "loop"
+        ));
+        let result = assemble_object("  BNE $1234\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::AbsoluteBranch(
+                0x1234,
+                _
+            )))
+        ))
+    }
+
+    /// Inputs that have made the assembler panic, and lines shuffled together at random.
+    #[test]
+    fn never_panics() {
+        let corpus = [
+            "",
+            "\n",
+            "RTS",
+            "  RTS",
+            ":\n",
+            "  JMP\n",
+            "  STZ $12345\n",
+            "  LDA #$123\n",
+            "  .org $FFFF\n  JMP $1234\n",
+            "  .org $FFFA\n  RTS\n  .vectors a, a, a\na:\n",
+            "  .vectors a, a, a\n  .vectors a, a, a\na:\n",
+            "  BNE nowhere\n",
+            "  .import far\n  BNE far\n",
+            "  .importzp zp\n  .export zp\n",
+            "  .entry\n",
+            "  .segment \"\n",
+            "\u{e9}\u{301}:\n  JMP \u{e9}\n",
+            "  \u{1F600}\n",
+            "\r\n\r\n  RTS\r\n",
+            "  .basicstub start\n  .org $FFF8\nstart:\n",
+        ];
+        let pieces = [
+            "loop:",
+            "  RTS",
+            "  JMP loop",
+            "  BNE loop",
+            "  BRA $10",
+            "  STZ $0300",
+            "  STZ $12",
+            "  LDA #<loop",
+            "  LDA #>$FFFF",
+            "  LDA #loop",
+            "  .org $FFFD",
+            "  .org $0000",
+            "  .segment \"RODATA\"",
+            "  .export loop",
+            "  .import loop",
+            "  .importzp zp",
+            "  STZ zp",
+            "  .global loop",
+            "  .entry loop",
+            "  .init loop",
+            "  .basicstub loop",
+            "  .vectors loop, loop, loop",
+            "  .foo",
+            "  JMP ($12)",
+            "  JMP",
+            "loop",
+            "$",
+            "  LDA #$",
+            ";",
+            "\t",
+            "  BNE",
+            "  JMP loop2",
+            "  LDA loop,X",
+            "  .pagecheck",
+            "  JMP (loop)",
+        ];
+        for input in &corpus {
+            let _ = assemble(input);
+        }
+        let mut state: u32 = 0x1234_5678;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as usize
+        };
+        for _ in 0..500 {
+            let mut input = String::new();
+            for _ in 0..next() % 12 {
+                input.push_str(pieces[next() % pieces.len()]);
+                if next() % 8 != 0 {
+                    input.push('\n');
+                }
+            }
+            let _ = assemble(&input);
+            let _ = assemble_with_config(&input, &segment_config());
+        }
+    }
+
+    #[test]
+    fn warnings() {
+        let input = "  STZ ptr\nrts:\n  .org $0300\n  .org $0300\n  .segment \"ZEROPAGE\"\nptr:\n";
+        let (_, warnings) = assemble_object_with_options(input, &Options::default()).unwrap();
+        let found: Vec<_> = warnings.iter().map(|w| (w.lint, w.span)).collect();
+        assert_eq!(
+            vec![
+                (Lint::ImplicitAbsolute, Span::new(6, 9)),
+                (Lint::MnemonicLabel, Span::new(10, 13)),
+                (Lint::UnusedLabel, Span::new(10, 13)),
+                (Lint::RedundantOrg, Span::new(35, 40)),
+            ],
+            found
+        )
+    }
+
+    #[test]
+    fn warning_levels() {
+        let input = "  .warning off unused_label\nloop:\n  .warning on\nrts:\n  RTS\n";
+        let (_, warnings) = assemble_object_with_options(input, &Options::default()).unwrap();
+        assert_eq!(
+            vec![Lint::MnemonicLabel, Lint::UnusedLabel],
+            warnings.iter().map(|w| w.lint).collect::<Vec<_>>()
+        );
+
+        let mut options = Options::default();
+        options.lints.set(Lint::UnusedLabel, Level::Deny);
+        let errors = assemble_object_with_options("loop:\n  RTS\n", &options).unwrap_err();
+        let diagnostic = errors[0].diagnostic("").unwrap();
+        assert_eq!(
+            "label loop is never used [unused_label]",
+            diagnostic.message
+        );
+        assert_eq!(Severity::Error, diagnostic.severity);
+
+        options.lints.set_all(Level::Allow);
+        let (_, warnings) = assemble_object_with_options("rts:\n", &options).unwrap();
+        assert!(warnings.is_empty())
+    }
+
+    #[test]
+    fn nmos_instructions() {
+        let options = Options {
+            cpu: Cpu::Nmos6502,
+            ..Options::default()
+        };
+        let errors =
+            assemble_object_with_options("  STZ $12\n  BRA $1234\n", &options).unwrap_err();
+        assert!(matches!(
+            &errors[..],
+            [
+                Error::CodeGenError(code_generator::Error::UnsupportedInstruction(
+                    parser::Mnemonic::STZ,
+                    Cpu::Nmos6502,
+                    _
+                )),
+                Error::CodeGenError(code_generator::Error::UnsupportedInstruction(
+                    parser::Mnemonic::BRA,
+                    Cpu::Nmos6502,
+                    _
+                ))
+            ]
+        ))
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        let input = "  JMP ($10FF)\n  JMP (vector)\n  JMP (other)\n  .segment \"RODATA\"\n  .org $12FF\nvector:\nother:\n";
+        let options = Options {
+            cpu: Cpu::Nmos6502,
+            ..Options::default()
+        };
+        let (_, warnings) =
+            assemble_layout_with_options(input, &segment_config(), &options).unwrap();
+        let found: Vec<_> = warnings
+            .iter()
+            .filter(|w| w.lint == Lint::JmpIndirectPageWrap)
+            .map(|w| (w.span, w.message.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    Span::new(6, 13),
+                    "JMP ($10FF) reads the high byte of its target from $1000 on the 6502"
+                ),
+                (
+                    Span::new(20, 28),
+                    "JMP ($12FF) reads the high byte of its target from $1200 on the 6502"
+                ),
+                (
+                    Span::new(35, 42),
+                    "JMP ($12FF) reads the high byte of its target from $1200 on the 6502"
+                ),
+            ],
+            found
+        );
+
+        let (_, warnings) =
+            assemble_layout_with_options(input, &segment_config(), &Options::default()).unwrap();
+        assert!(warnings.iter().all(|w| w.lint != Lint::JmpIndirectPageWrap))
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap_relocatable() {
+        let config = "CODE: start = $80FB, size = $10;".parse().unwrap();
+        let mut options = Options {
+            cpu: Cpu::Nmos6502,
+            ..Options::default()
+        };
+        options.lints.set(Lint::JmpIndirectPageWrap, Level::Deny);
+        let input = "  JMP (vector)\n  RTS\nvector:\n";
+        let errors = assemble_layout_with_options(input, &config, &options).unwrap_err();
+        assert!(matches!(
+            &errors[..],
+            [Error::DeniedWarning(w)] if w.lint == Lint::JmpIndirectPageWrap
+        ));
+
+        // The same link step as `assemble`, which always targets the 65C02.
+        let input = "  .warning deny jmp_indirect_page_wrap\n  JMP ($12FF)\n";
+        let options = Options {
+            cpu: Cpu::Nmos6502,
+            ..Options::default()
+        };
+        let (object, _) = assemble_object_with_options(input, &options).unwrap();
+        let result = linker::link(&LinkerConfig::default(), &[object]);
+        assert!(matches!(
+            result,
+            Err(linker::Error::DeniedCheck(ref f))
+                if f.check.kind == CheckKind::JmpIndirectPageWrap && f.operand == 0x12FF
+        ));
+        assert!(assemble(input).is_ok())
+    }
+
+    #[test]
+    fn page_check() {
+        let input = "  .pagecheck\nloop:\n  BNE loop\n  BNE done\n  LDA table,X\n  LDA page,Y\n  .endpagecheck\n  LDA table,X\ndone:\n  .segment \"RODATA\"\ntable:\n  RTS\npage:\n";
+        let config = "CODE: start = $80FE, size = $20;\nRODATA: start = $90FF, size = $10;"
+            .parse()
+            .unwrap();
+        let (_, warnings) =
+            assemble_layout_with_options(input, &config, &Options::default()).unwrap();
+        let found: Vec<_> = warnings
+            .iter()
+            .filter(|w| w.lint == Lint::PageCross)
+            .map(|w| (w.span, w.message.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    Span::new(25, 29),
+                    "branch to $80FE crosses a page, taking it costs an extra cycle"
+                ),
+                (
+                    Span::new(47, 54),
+                    "indexed read from $90FF can cross a page, which costs an extra cycle"
+                ),
+            ],
+            found
+        );
+
+        let input = format!("  .warning deny page_cross\n{}", input);
+        let errors =
+            assemble_layout_with_options(&input, &config, &Options::default()).unwrap_err();
+        assert_eq!(2, errors.len())
+    }
+
+    #[test]
+    fn page_check_indexes() {
+        let input = "  .warning deny page_cross\n  .pagecheck 4\n  LDA $C010,X\n  LDA $C0FC,Y\n  LDA $C0FD,X\n";
+        let result = assemble(input);
+        assert!(matches!(
+            result,
+            Err(Error::LinkError(linker::Error::DeniedCheck(ref f)))
+                if f.check.kind == CheckKind::IndexedPageCross { indexes: 4 }
+                    && f.operand == 0xC0FD
+        ));
+        assert!(assemble(&input.replace("  LDA $C0FD,X\n", "")).is_ok())
+    }
+
+    #[test]
+    fn denied_page_cross() {
+        let input = "  .warning deny page_cross\n  .pagecheck\n  .org $80FE\nloop:\n  BNE loop\n";
+        let result = assemble(input);
+        assert!(matches!(
+            result,
+            Err(Error::LinkError(linker::Error::DeniedCheck(ref f)))
+                if f.check.kind == CheckKind::BranchPageCross && f.operand == 0x80FE
+        ));
+        let result = assemble_layout(input, &LinkerConfig::default());
+        assert!(matches!(
+            result,
+            Err(Error::LinkError(linker::Error::DeniedCheck(_)))
+        ));
+        assert!(assemble(&input.replace("deny", "warn")).is_ok())
+    }
+
+    #[test]
+    fn cycle_budget() {
+        let block = "  LDA #$00\n  LDA $0300,X\n  BNE done\ndone:\n  .endcycles\n  RTS\n";
+        assert!(assemble_object(&format!("  .cycles 8, 11\n{}", block)).is_ok());
+        let input = format!("  .cycles 10\n{}", block);
+        let result = assemble_object(&input);
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::CycleBudget(
+                8,
+                11,
+                0,
+                10,
+                _
+            )))
+        ));
+        let input = format!("  .cycles 9, 12\n{}", block);
+        let result = assemble_object(&input);
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::CycleBudget(
+                8,
+                11,
+                9,
+                12,
+                _
+            )))
+        ));
+
+        let input = format!("  .pagecheck\n  .cycles 9\n{}", block);
+        let result = assemble_object(&input);
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::CycleBudget(
+                8,
+                11,
+                0,
+                9,
+                _
+            )))
+        ));
+        let input = format!("  .warning deny page_cross\n{}", input);
+        assert!(assemble_object(&input).is_ok());
+
+        let result = assemble_object("  .cycles 10\n  RTS\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::UnmatchedCycles(
+                _
+            )))
+        ));
+        let result = assemble_object("  RTS\n  .endcycles\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::UnmatchedCycles(
+                _
+            )))
+        ))
+    }
+
+    #[test]
+    fn org_assemble() {
+        let input = "  JMP data\n  .org $0300\ndata:\n  RTS\n  .segment \"RODATA\"\n  JMP data\n";
+        let result = assemble_with_config(input, &segment_config()).unwrap();
+        assert_eq!(vec![0x4C, 0x00, 0x03], result[1].bytes);
+        assert_eq!(vec![0x4C, 0x00, 0x03], result[2].bytes);
+        assert_eq!(
+            Segment {
+                name: "ORG_0300".to_owned(),
+                start: 0x0300,
+                bytes: vec![0x60]
+            },
+            result[3]
+        )
+    }
+
+    #[test]
+    fn org_overlap() {
+        let input = "  RTS\n  .org $8000\n  RTS\n";
+        let result = assemble_with_config(input, &segment_config());
+        assert!(matches!(
+            result,
+            Err(Error::LinkError(linker::Error::Overlap(org, code, _))) if org == "ORG_8000" && code == "CODE"
+        ))
+    }
+
+    #[test]
+    fn duplicate_entry() {
+        let main = assemble_object("  .entry start\nstart:\n  RTS\n").unwrap();
+        let other = assemble_object("  .entry other\nother:\n  RTS\n").unwrap();
+        assert_eq!(
+            Err(linker::Error::DuplicateEntry),
+            linker::layout(&segment_config(), &[main, other], &[])
+        )
+    }
+
+    #[test]
+    fn undefined_entry() {
+        let result = assemble_object("  .entry nowhere\n  RTS\n");
+        assert!(matches!(
+            result,
+            Err(Error::CodeGenError(code_generator::Error::UndefinedSymbol(s, _))) if s == "nowhere"
+        ))
+    }
+
+    #[test]
+    fn byte_selection_assemble() {
+        let input =
+            "  LDA #<data\n  LDA #>data\n  LDA #>$1234\n  .segment \"RODATA\"\ndata:\n  RTS\n";
+        let result = assemble_with_config(input, &segment_config()).unwrap();
+        assert_eq!(vec![0xA9, 0x00, 0xA9, 0xC0, 0xA9, 0x12], result[1].bytes)
+    }
 }
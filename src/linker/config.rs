@@ -0,0 +1,320 @@
+use std::str::FromStr;
+
+use nom::branch::alt;
+use nom::bytes::complete::{is_not, tag};
+use nom::character::complete::{alpha1, alphanumeric1, char, digit1, hex_digit1, multispace1};
+use nom::combinator::{all_consuming, map, map_res, recognize, value};
+use nom::multi::{many0, separated_list1};
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
+use nom::Finish;
+
+type Input<'a> = &'a str;
+type IResult<'a, T> = nom::IResult<Input<'a>, T>;
+
+/// Placement of every segment in the 16-bit address space.
+///
+/// Can be built directly or parsed from text, one segment per entry:
+///
+/// ```text
+/// # comments run until the end of the line
+/// ZEROPAGE: start = $0000, size = $0100;
+/// CODE:     start = $C000, size = $3FFA;
+/// BANK1:    start = $8000, size = $4000, bank = 1;
+/// ```
+///
+/// Segments in different banks can share addresses, as only one of them is mapped in at a time.
+/// Segments without a bank are always mapped in and can't share addresses with any other.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LinkerConfig {
+    pub segments: Vec<SegmentConfig>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SegmentConfig {
+    pub name: String,
+    pub start: u16,
+    pub size: u32,
+    pub bank: Option<u32>,
+}
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum ConfigError {
+    #[error("syntax error in linker configuration on line {0}")]
+    Syntax(usize),
+    #[error("segment {segment} is missing the {attribute} attribute")]
+    MissingAttribute {
+        segment: String,
+        attribute: &'static str,
+    },
+    #[error("segment {segment} has unknown attribute {attribute}")]
+    UnknownAttribute { segment: String, attribute: String },
+    #[error("segment {0} is defined more than once")]
+    DuplicateSegment(String),
+    #[error("segment {0} does not fit in the address space")]
+    OutOfAddressSpace(String),
+    #[error("segments {0} and {1} overlap")]
+    Overlap(String, String),
+}
+
+pub const DEFAULT_SEGMENT: &str = "CODE";
+
+impl Default for LinkerConfig {
+    fn default() -> Self {
+        LinkerConfig {
+            segments: vec![SegmentConfig::new(DEFAULT_SEGMENT, 0, 0x10000)],
+        }
+    }
+}
+
+impl SegmentConfig {
+    pub fn new(name: &str, start: u16, size: u32) -> Self {
+        SegmentConfig {
+            name: name.to_owned(),
+            start,
+            size,
+            bank: None,
+        }
+    }
+
+    /// One past the last address, which can be past the address space for a bad size.
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.size as u64
+    }
+}
+
+impl LinkerConfig {
+    pub fn segment(&self, name: &str) -> Option<&SegmentConfig> {
+        self.segments.iter().find(|s| s.name == name)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (idx, segment) in self.segments.iter().enumerate() {
+            if segment.end() > 0x10000 {
+                return Err(ConfigError::OutOfAddressSpace(segment.name.clone()));
+            }
+            for other in &self.segments[..idx] {
+                if other.name == segment.name {
+                    return Err(ConfigError::DuplicateSegment(segment.name.clone()));
+                }
+                if segment.size > 0
+                    && other.size > 0
+                    && (segment.bank.is_none()
+                        || other.bank.is_none()
+                        || segment.bank == other.bank)
+                    && (segment.start as u64) < other.end()
+                    && (other.start as u64) < segment.end()
+                {
+                    return Err(ConfigError::Overlap(
+                        other.name.clone(),
+                        segment.name.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LinkerConfig {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (_, entries) = all_consuming(terminated(many0(preceded(ignored, entry)), ignored))(s)
+            .finish()
+            .map_err(|e| ConfigError::Syntax(line_of(s, e.input)))?;
+        let segments = entries
+            .into_iter()
+            .map(|(name, attributes)| {
+                let mut start = None;
+                let mut size = None;
+                let mut bank = None;
+                for (attribute, value) in attributes {
+                    match attribute {
+                        "start" if value <= 0xFFFF => start = Some(value as u16),
+                        "size" => size = Some(value),
+                        "bank" => bank = Some(value),
+                        "start" => return Err(ConfigError::OutOfAddressSpace(name.to_owned())),
+                        other => {
+                            return Err(ConfigError::UnknownAttribute {
+                                segment: name.to_owned(),
+                                attribute: other.to_owned(),
+                            })
+                        }
+                    }
+                }
+                let missing = |attribute| ConfigError::MissingAttribute {
+                    segment: name.to_owned(),
+                    attribute,
+                };
+                Ok(SegmentConfig {
+                    name: name.to_owned(),
+                    start: start.ok_or_else(|| missing("start"))?,
+                    size: size.ok_or_else(|| missing("size"))?,
+                    bank,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let config = LinkerConfig { segments };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn line_of(full: &str, rest: &str) -> usize {
+    full[..full.len() - rest.len()].matches('\n').count() + 1
+}
+
+fn ignored(i: Input) -> IResult<()> {
+    value(
+        (),
+        many0(alt((
+            value((), multispace1),
+            value((), pair(char('#'), many0(is_not("\n")))),
+        ))),
+    )(i)
+}
+
+fn identifier(i: Input<'_>) -> IResult<'_, &str> {
+    recognize(pair(alpha1, many0(alt((alphanumeric1, tag("_"))))))(i)
+}
+
+fn number(i: Input) -> IResult<u32> {
+    alt((
+        map_res(preceded(char('$'), hex_digit1), |s| {
+            u32::from_str_radix(s, 16)
+        }),
+        map_res(digit1, u32::from_str),
+    ))(i)
+}
+
+fn attribute(i: Input<'_>) -> IResult<'_, (&str, u32)> {
+    separated_pair(
+        delimited(ignored, identifier, ignored),
+        char('='),
+        delimited(ignored, number, ignored),
+    )(i)
+}
+
+fn entry(i: Input<'_>) -> IResult<'_, (&str, Vec<(&str, u32)>)> {
+    map(
+        tuple((
+            identifier,
+            preceded(ignored, char(':')),
+            separated_list1(char(','), attribute),
+            char(';'),
+        )),
+        |(name, _, attributes, _)| (name, attributes),
+    )(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_success() {
+        let input = "# memory map\nZEROPAGE: start = $0000, size = $100;\n\nCODE: start=$8000,size=32762; # rest\n";
+        let result = LinkerConfig::from_str(input);
+        assert_eq!(
+            Ok(LinkerConfig {
+                segments: vec![
+                    SegmentConfig::new("ZEROPAGE", 0x0000, 0x100),
+                    SegmentConfig::new("CODE", 0x8000, 0x7FFA),
+                ]
+            }),
+            result
+        )
+    }
+
+    #[test]
+    fn parse_syntax_error() {
+        let input = "CODE: start = $8000, size = $100;\nDATA start = $0200;\n";
+        let result = LinkerConfig::from_str(input);
+        assert_eq!(Err(ConfigError::Syntax(2)), result)
+    }
+
+    #[test]
+    fn parse_missing_attribute() {
+        let input = "CODE: start = $8000;";
+        let result = LinkerConfig::from_str(input);
+        assert_eq!(
+            Err(ConfigError::MissingAttribute {
+                segment: "CODE".to_owned(),
+                attribute: "size"
+            }),
+            result
+        )
+    }
+
+    #[test]
+    fn validate_overlap() {
+        let config = LinkerConfig {
+            segments: vec![
+                SegmentConfig::new("CODE", 0x8000, 0x1000),
+                SegmentConfig::new("RODATA", 0x8FFF, 0x10),
+            ],
+        };
+        assert_eq!(
+            Err(ConfigError::Overlap("CODE".to_owned(), "RODATA".to_owned())),
+            config.validate()
+        )
+    }
+
+    #[test]
+    fn parse_banks() {
+        let input = "BANK0: start = $8000, size = $4000, bank = 0;\nBANK1: start = $8000, size = $4000, bank = 1;\n";
+        let result = LinkerConfig::from_str(input).unwrap();
+        assert_eq!(Some(1), result.segments[1].bank)
+    }
+
+    #[test]
+    fn validate_same_bank_overlap() {
+        let input = "BANK0: start = $8000, size = $4000, bank = 0;\nDATA0: start = $BF00, size = $100, bank = 0;\n";
+        let result = LinkerConfig::from_str(input);
+        assert_eq!(
+            Err(ConfigError::Overlap("BANK0".to_owned(), "DATA0".to_owned())),
+            result
+        )
+    }
+
+    #[test]
+    fn validate_unbanked_overlap() {
+        let input =
+            "CODE: start = $8000, size = $4000;\nBANK0: start = $8000, size = $4000, bank = 0;\n";
+        let result = LinkerConfig::from_str(input);
+        assert_eq!(
+            Err(ConfigError::Overlap("CODE".to_owned(), "BANK0".to_owned())),
+            result
+        )
+    }
+
+    #[test]
+    fn validate_adjacent() {
+        let config = LinkerConfig {
+            segments: vec![
+                SegmentConfig::new("CODE", 0x8000, 0x7FFA),
+                SegmentConfig::new("VECTORS", 0xFFFA, 6),
+            ],
+        };
+        assert_eq!(Ok(()), config.validate())
+    }
+
+    #[test]
+    fn validate_out_of_address_space() {
+        let config = LinkerConfig {
+            segments: vec![SegmentConfig::new("CODE", 0xFFFA, 7)],
+        };
+        assert_eq!(
+            Err(ConfigError::OutOfAddressSpace("CODE".to_owned())),
+            config.validate()
+        )
+    }
+
+    #[test]
+    fn parse_size_past_address_space() {
+        assert_eq!(
+            Err(ConfigError::OutOfAddressSpace("CODE".to_owned())),
+            "CODE: start = $8000, size = $FFFFFFFF;".parse::<LinkerConfig>()
+        )
+    }
+}
@@ -0,0 +1,910 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+pub use config::{ConfigError, LinkerConfig, SegmentConfig, DEFAULT_SEGMENT};
+
+use crate::archive::Archive;
+use crate::cpu::Cycles;
+use crate::diagnostic::Span;
+use crate::object::{self, Check, CheckKind, Line, Object, RelocationKind, RelocationTarget};
+
+mod config;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("module {module}: {source}")]
+    InvalidObject {
+        module: usize,
+        source: object::Error,
+    },
+    #[error("segment \"{0}\" is not defined in the linker configuration")]
+    UnknownSegment(String),
+    #[error("segment {segment} needs {needed} bytes but only has room for {size}")]
+    SegmentOverflow {
+        segment: String,
+        size: u32,
+        needed: usize,
+    },
+    #[error("symbol {0} is imported but not exported by any module")]
+    UnresolvedImport(String, Option<Location>),
+    #[error("symbol {0} is exported by more than one module")]
+    DuplicateExport(String),
+    #[error("more than one module sets an entry point")]
+    DuplicateEntry,
+    /// The location is the first line placed in the `.org` fragment.
+    #[error("segment {0} overlaps segment {1}")]
+    Overlap(String, String, Option<Location>),
+    #[error("{target} resolves to {value:#06X}, which is not on the zero page")]
+    ZeroPageOutOfRange {
+        target: String,
+        value: u16,
+        location: Location,
+    },
+    #[error("branch to {target} at {address:#06X} is {distance} bytes away, more than a branch can reach")]
+    BranchOutOfRange {
+        target: String,
+        address: u16,
+        distance: i32,
+        location: Location,
+    },
+    /// A check whose lint was set to deny where the instruction is.
+    #[error("{}", .0.message())]
+    DeniedCheck(FailedCheck),
+}
+
+impl Error {
+    /// Where in the source of the linked modules the error is, if it comes from one place.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Error::UnresolvedImport(_, location) | Error::Overlap(_, _, location) => *location,
+            Error::ZeroPageOutOfRange { location, .. }
+            | Error::BranchOutOfRange { location, .. } => Some(*location),
+            Error::DeniedCheck(failed) => Some(Location {
+                module: failed.module,
+                span: Span::new(
+                    failed.check.source_start as usize,
+                    failed.check.source_end as usize,
+                ),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A byte range in the source of the module at index `module` of the linked modules, libraries
+/// included.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Location {
+    pub module: usize,
+    pub span: Span,
+}
+
+/// A segment's final contents, placed at its start address.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Segment {
+    pub name: String,
+    pub start: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// Every segment placed at its final address, with the symbols and relocations that went into
+/// it. Relocations against imports no module exports are left for a later stage, such as an o65
+/// loader, and their bytes only hold the addend.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Layout {
+    pub segments: Vec<Segment>,
+    pub symbols: Vec<PlacedSymbol>,
+    pub relocations: Vec<PlacedRelocation>,
+    pub unresolved: Vec<String>,
+    pub lines: Vec<PlacedLine>,
+    /// The address of the `.entry` symbol, if a module set one.
+    pub entry: Option<u16>,
+    /// The addresses of the `.init` symbols of all modules, in order.
+    pub inits: Vec<u16>,
+    /// The start addresses of the `.org` fragments, in the order they were first used.
+    pub orgs: Vec<u16>,
+    pub failed_checks: Vec<FailedCheck>,
+}
+
+/// A check of the module at index `module` that failed for the instruction at `address`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct FailedCheck {
+    pub module: usize,
+    pub check: Check,
+    pub address: u16,
+    /// The operand of the instruction, or the target of a branch.
+    pub operand: u16,
+}
+
+impl FailedCheck {
+    pub fn message(&self) -> String {
+        match self.check.kind {
+            CheckKind::JmpIndirectPageWrap => format!(
+                "JMP (${:04X}) reads the high byte of its target from ${:04X} on the 6502",
+                self.operand,
+                self.operand & 0xFF00
+            ),
+            CheckKind::BranchPageCross => format!(
+                "branch to ${:04X} crosses a page, taking it costs an extra cycle",
+                self.operand
+            ),
+            CheckKind::IndexedPageCross { .. } => format!(
+                "indexed read from ${:04X} can cross a page, which costs an extra cycle",
+                self.operand
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PlacedSymbol {
+    pub name: String,
+    pub segment: String,
+    pub address: u16,
+    pub exported: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PlacedRelocation {
+    pub address: u16,
+    pub kind: RelocationKind,
+    pub target: PlacedTarget,
+    /// The final value for resolved targets, the addend for undefined ones.
+    pub value: u16,
+}
+
+/// A source line of the module at index `module` of the linked modules, libraries included,
+/// the address its `size` bytes were placed at and the cycles it takes.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PlacedLine {
+    pub module: usize,
+    pub source_offset: u32,
+    pub segment: String,
+    pub address: u16,
+    pub size: u16,
+    pub cycles: Option<Cycles>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum PlacedTarget {
+    /// An address in the named segment.
+    Segment(String),
+    Undefined(String),
+}
+
+/// Places every module's fragments in the segments, in the order the configuration lists the
+/// segments and the modules are given, and fills in all relocations.
+pub fn link(config: &LinkerConfig, objects: &[Object]) -> Result<Vec<Segment>, Error> {
+    link_with_libraries(config, objects, &[])
+}
+
+/// Like [`link`], but first adds the library modules that export a symbol the modules linked so
+/// far still import. Libraries are searched in order, each until it has nothing more to
+/// contribute, so a library can only satisfy imports of earlier libraries, not later ones.
+/// Included library modules are placed after `objects`, in the order they were pulled in.
+pub fn link_with_libraries(
+    config: &LinkerConfig,
+    objects: &[Object],
+    libraries: &[Archive],
+) -> Result<Vec<Segment>, Error> {
+    let selected = select(objects, libraries);
+    let layout = layout_objects(config, &selected)?;
+    if let Some(name) = layout.unresolved.first() {
+        let location = unresolved_location(&selected, name);
+        return Err(Error::UnresolvedImport(name.clone(), location));
+    }
+    denied_checks(&layout)?;
+    Ok(layout.segments)
+}
+
+/// The first failed check that was set to deny, as an error.
+pub fn denied_checks(layout: &Layout) -> Result<(), Error> {
+    match layout.failed_checks.iter().find(|f| f.check.deny) {
+        Some(failed) => Err(Error::DeniedCheck(failed.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Like [`link_with_libraries`], but keeps imports that no module exports, and leaves the failed
+/// checks to the caller.
+pub fn layout(
+    config: &LinkerConfig,
+    objects: &[Object],
+    libraries: &[Archive],
+) -> Result<Layout, Error> {
+    layout_objects(config, &select(objects, libraries))
+}
+
+/// `objects` followed by the library modules they need.
+fn select<'a>(objects: &'a [Object], libraries: &'a [Archive]) -> Vec<&'a Object> {
+    let mut selected: Vec<&Object> = objects.iter().collect();
+    for library in libraries {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for module in &library.modules {
+                if selected.iter().any(|o| std::ptr::eq(*o, &module.object)) {
+                    continue;
+                }
+                let undefined = undefined_imports(&selected);
+                if module
+                    .object
+                    .symbols
+                    .iter()
+                    .any(|s| s.exported && undefined.contains(s.name.as_str()))
+                {
+                    selected.push(&module.object);
+                    changed = true;
+                }
+            }
+        }
+    }
+    selected
+}
+
+/// The first use of an import no module exports, or where it is imported if it is not used.
+fn unresolved_location(objects: &[&Object], name: &str) -> Option<Location> {
+    let location = |module, start: u32, end: u32| Location {
+        module,
+        span: Span::new(start as usize, end as usize),
+    };
+    let used = objects.iter().enumerate().find_map(|(module, object)| {
+        object
+            .segments
+            .iter()
+            .flat_map(|s| &s.relocations)
+            .find(|r| matches!(&r.target, RelocationTarget::Import(n) if n == name))
+            .map(|r| location(module, r.source_start, r.source_end))
+    });
+    used.or_else(|| {
+        objects.iter().enumerate().find_map(|(module, object)| {
+            object
+                .imports
+                .iter()
+                .find(|i| i.name == name)
+                .map(|i| location(module, i.source_start, i.source_end))
+        })
+    })
+}
+
+fn undefined_imports<'a>(objects: &[&'a Object]) -> HashSet<&'a str> {
+    let exported: HashSet<&str> = objects
+        .iter()
+        .flat_map(|o| &o.symbols)
+        .filter(|s| s.exported)
+        .map(|s| s.name.as_str())
+        .collect();
+    objects
+        .iter()
+        .flat_map(|o| &o.imports)
+        .map(|i| i.name.as_str())
+        .filter(|name| !exported.contains(name))
+        .collect()
+}
+
+fn layout_objects(config: &LinkerConfig, objects: &[&Object]) -> Result<Layout, Error> {
+    config.validate()?;
+    for (module, object) in objects.iter().enumerate() {
+        object
+            .validate()
+            .map_err(|source| Error::InvalidObject { module, source })?;
+        let relocation_targets = object
+            .segments
+            .iter()
+            .flat_map(|s| &s.relocations)
+            .filter_map(|r| match &r.target {
+                RelocationTarget::Segment(name) => Some(name),
+                RelocationTarget::Import(_) => None,
+            });
+        let used_segments = object
+            .segments
+            .iter()
+            .map(|s| &s.name)
+            .chain(object.symbols.iter().map(|s| &s.segment))
+            .chain(relocation_targets)
+            .chain(object.lines.iter().map(|l| &l.segment))
+            .chain(object.checks.iter().map(|c| &c.segment));
+        for name in used_segments {
+            let is_org = object
+                .segments
+                .iter()
+                .any(|s| &s.name == name && s.org.is_some());
+            if config.segment(name).is_none() && !is_org {
+                return Err(Error::UnknownSegment(name.clone()));
+            }
+        }
+    }
+
+    // Start address of each module's fragment of every segment, by module index.
+    let mut bases: Vec<HashMap<&str, u16>> = vec![HashMap::new(); objects.len()];
+    let mut segments = config
+        .segments
+        .iter()
+        .map(|segment_config| {
+            let mut bytes = vec![];
+            for (object, bases) in objects.iter().zip(bases.iter_mut()) {
+                let base = segment_config.start as usize + bytes.len();
+                bases.insert(segment_config.name.as_str(), base as u16);
+                if let Some(fragment) = object
+                    .segments
+                    .iter()
+                    .find(|s| s.name == segment_config.name)
+                {
+                    bytes.extend(&fragment.bytes);
+                }
+            }
+            if bytes.len() as u32 > segment_config.size {
+                return Err(Error::SegmentOverflow {
+                    segment: segment_config.name.clone(),
+                    size: segment_config.size,
+                    needed: bytes.len(),
+                });
+            }
+            Ok(Segment {
+                name: segment_config.name.clone(),
+                start: segment_config.start,
+                bytes,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut orgs: Vec<(&str, u16)> = vec![];
+    for fragment in objects.iter().flat_map(|o| &o.segments) {
+        if let Some(org) = fragment.org {
+            if !orgs.contains(&(fragment.name.as_str(), org)) {
+                orgs.push((&fragment.name, org));
+            }
+        }
+    }
+    let org_starts = orgs.iter().map(|(_, start)| *start).collect();
+    for (name, start) in orgs {
+        let mut bytes = vec![];
+        for (object, bases) in objects.iter().zip(bases.iter_mut()) {
+            if let Some(fragment) = object
+                .segments
+                .iter()
+                .find(|s| s.name == name && s.org == Some(start))
+            {
+                bases.insert(name, (start as usize + bytes.len()) as u16);
+                bytes.extend(&fragment.bytes);
+            }
+        }
+        let size = 0x10000 - start as u32;
+        if bytes.len() as u32 > size {
+            return Err(Error::SegmentOverflow {
+                segment: name.to_owned(),
+                size,
+                needed: bytes.len(),
+            });
+        }
+        let end = start as usize + bytes.len();
+        if let Some(other) = segments.iter().find(|s| {
+            !bytes.is_empty()
+                && !s.bytes.is_empty()
+                && (start as usize) < s.start as usize + s.bytes.len()
+                && (s.start as usize) < end
+        }) {
+            let location = objects.iter().enumerate().find_map(|(module, object)| {
+                let in_fragment = |l: &&Line| l.segment == name && l.size > 0;
+                object.lines.iter().find(in_fragment).map(|line| Location {
+                    module,
+                    span: Span::new(line.source_offset as usize, line.source_offset as usize),
+                })
+            });
+            return Err(Error::Overlap(
+                name.to_owned(),
+                other.name.clone(),
+                location,
+            ));
+        }
+        segments.push(Segment {
+            name: name.to_owned(),
+            start,
+            bytes,
+        });
+    }
+
+    let mut symbols = vec![];
+    let mut exports: HashMap<&str, (u16, &str)> = HashMap::new();
+    for (object, bases) in objects.iter().zip(&bases) {
+        for symbol in &object.symbols {
+            let address = bases[symbol.segment.as_str()].wrapping_add(symbol.offset);
+            if symbol.exported
+                && exports
+                    .insert(&symbol.name, (address, &symbol.segment))
+                    .is_some()
+            {
+                return Err(Error::DuplicateExport(symbol.name.clone()));
+            }
+            symbols.push(PlacedSymbol {
+                name: symbol.name.clone(),
+                segment: symbol.segment.clone(),
+                address,
+                exported: symbol.exported,
+            });
+        }
+    }
+    let mut entry = None;
+    let mut inits = vec![];
+    for (object, bases) in objects.iter().zip(&bases) {
+        let address_of = |name: &String| match object.symbols.iter().find(|s| &s.name == name) {
+            Some(symbol) => Ok(bases[symbol.segment.as_str()].wrapping_add(symbol.offset)),
+            None => match exports.get(name.as_str()) {
+                Some((address, _)) => Ok(*address),
+                None => Err(Error::UnresolvedImport(
+                    name.clone(),
+                    unresolved_location(objects, name),
+                )),
+            },
+        };
+        if let Some(name) = &object.entry {
+            if entry.is_some() {
+                return Err(Error::DuplicateEntry);
+            }
+            entry = Some(address_of(name)?);
+        }
+        for name in &object.inits {
+            inits.push(address_of(name)?);
+        }
+    }
+
+    let mut unresolved: Vec<String> = vec![];
+    for import in objects.iter().flat_map(|o| &o.imports) {
+        if !exports.contains_key(import.name.as_str()) && !unresolved.contains(&import.name) {
+            unresolved.push(import.name.clone());
+        }
+    }
+
+    let mut relocations = vec![];
+    for (module, (object, bases)) in objects.iter().zip(&bases).enumerate() {
+        for fragment in &object.segments {
+            let base = bases[fragment.name.as_str()];
+            let segment = segments
+                .iter_mut()
+                .find(|s| s.name == fragment.name)
+                .ok_or_else(|| Error::UnknownSegment(fragment.name.clone()))?;
+            for relocation in &fragment.relocations {
+                let (name, target) = match &relocation.target {
+                    RelocationTarget::Segment(name) => {
+                        (name, Some((bases[name.as_str()], name.as_str())))
+                    }
+                    RelocationTarget::Import(name) => (name, exports.get(name.as_str()).copied()),
+                };
+                let offset = base
+                    .wrapping_sub(segment.start)
+                    .wrapping_add(relocation.offset);
+                let location = Location {
+                    module,
+                    span: Span::new(
+                        relocation.source_start as usize,
+                        relocation.source_end as usize,
+                    ),
+                };
+                let (target, value) = match target {
+                    Some((address, target_segment)) => {
+                        let mut value = address.wrapping_add(relocation.addend);
+                        if relocation.kind == RelocationKind::ZeroPage && value > 0xFF {
+                            return Err(Error::ZeroPageOutOfRange {
+                                target: name.clone(),
+                                value,
+                                location,
+                            });
+                        }
+                        if relocation.kind == RelocationKind::Relative {
+                            let next = segment.start as i32 + offset as i32 + 1;
+                            let distance = value as i32 - next;
+                            if i8::try_from(distance).is_err() {
+                                return Err(Error::BranchOutOfRange {
+                                    target: name.clone(),
+                                    address: value,
+                                    distance,
+                                    location,
+                                });
+                            }
+                            value = distance as u16;
+                        }
+                        (PlacedTarget::Segment(target_segment.to_owned()), value)
+                    }
+                    None if unresolved.contains(name) => {
+                        (PlacedTarget::Undefined(name.clone()), relocation.addend)
+                    }
+                    None => return Err(Error::UnresolvedImport(name.clone(), Some(location))),
+                };
+                patch(&mut segment.bytes, offset as usize, relocation.kind, value);
+                relocations.push(PlacedRelocation {
+                    address: segment.start.wrapping_add(offset),
+                    kind: relocation.kind,
+                    target,
+                    value,
+                });
+            }
+        }
+    }
+
+    let lines = objects
+        .iter()
+        .zip(&bases)
+        .enumerate()
+        .flat_map(|(module, (object, bases))| {
+            object.lines.iter().map(move |line| PlacedLine {
+                module,
+                source_offset: line.source_offset,
+                segment: line.segment.clone(),
+                address: bases[line.segment.as_str()].wrapping_add(line.offset),
+                size: line.size,
+                cycles: line.cycles,
+            })
+        })
+        .collect();
+
+    let mut failed_checks = vec![];
+    for (module, (object, bases)) in objects.iter().zip(&bases).enumerate() {
+        for check in &object.checks {
+            let address = bases[check.segment.as_str()].wrapping_add(check.offset);
+            let segment = segments
+                .iter()
+                .find(|s| s.name == check.segment)
+                .ok_or_else(|| Error::UnknownSegment(check.segment.clone()))?;
+            let at = address.wrapping_sub(segment.start) as usize + 1;
+            let (operand, failed) = match (check.kind, segment.bytes.get(at..)) {
+                (CheckKind::BranchPageCross, Some([offset, ..])) => {
+                    let next = address.wrapping_add(2);
+                    let target = next.wrapping_add(*offset as i8 as u16);
+                    (target, target >> 8 != next >> 8)
+                }
+                (CheckKind::JmpIndirectPageWrap, Some([low, high, ..])) => {
+                    (u16::from_le_bytes([*low, *high]), *low == 0xFF)
+                }
+                (CheckKind::IndexedPageCross { indexes }, Some([low, high, ..])) => {
+                    let base = u16::from_le_bytes([*low, *high]);
+                    (base, *low as u32 + indexes as u32 > 0x100)
+                }
+                _ => continue,
+            };
+            if failed {
+                failed_checks.push(FailedCheck {
+                    module,
+                    check: check.clone(),
+                    address,
+                    operand,
+                });
+            }
+        }
+    }
+
+    Ok(Layout {
+        segments,
+        symbols,
+        relocations,
+        unresolved,
+        lines,
+        entry,
+        inits,
+        orgs: org_starts,
+        failed_checks,
+    })
+}
+
+fn patch(bytes: &mut [u8], at: usize, kind: RelocationKind, value: u16) {
+    let [low, high] = value.to_le_bytes();
+    match kind {
+        RelocationKind::Word => bytes[at..at + 2].copy_from_slice(&[low, high]),
+        RelocationKind::ZeroPage | RelocationKind::Low | RelocationKind::Relative => {
+            bytes[at] = low
+        }
+        RelocationKind::High => bytes[at] = high,
+        RelocationKind::Decimal => {
+            bytes[at..at + 5].copy_from_slice(format!("{:>5}", value).as_bytes())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{AddressSize, Import, ObjectSegment, Relocation, Symbol};
+
+    fn config() -> LinkerConfig {
+        LinkerConfig {
+            segments: vec![
+                SegmentConfig::new("ZEROPAGE", 0x0000, 0x100),
+                SegmentConfig::new("CODE", 0x8000, 8),
+            ],
+        }
+    }
+
+    fn object(bytes: Vec<u8>, relocations: Vec<Relocation>, symbols: Vec<Symbol>) -> Object {
+        Object {
+            segments: vec![ObjectSegment {
+                name: "CODE".to_owned(),
+                org: None,
+                bytes,
+                relocations: relocations.clone(),
+            }],
+            symbols,
+            imports: relocations
+                .iter()
+                .filter_map(|r| match &r.target {
+                    RelocationTarget::Import(name) => Some(Import {
+                        name: name.clone(),
+                        size: AddressSize::Absolute,
+                        source_start: 0,
+                        source_end: 0,
+                    }),
+                    RelocationTarget::Segment(_) => None,
+                })
+                .collect(),
+            lines: vec![],
+            entry: None,
+            inits: vec![],
+            checks: vec![],
+        }
+    }
+
+    fn jmp_relocation(target: RelocationTarget, addend: u16) -> Relocation {
+        Relocation {
+            offset: 1,
+            kind: RelocationKind::Word,
+            target,
+            addend,
+            source_start: 6,
+            source_end: 11,
+        }
+    }
+
+    fn location(module: usize) -> Option<Location> {
+        Some(Location {
+            module,
+            span: Span::new(6, 11),
+        })
+    }
+
+    fn symbol(name: &str, offset: u16) -> Symbol {
+        Symbol {
+            name: name.to_owned(),
+            segment: "CODE".to_owned(),
+            offset,
+            exported: true,
+        }
+    }
+
+    #[test]
+    fn link_success() {
+        let objects = vec![
+            object(
+                vec![0x4C, 0x00, 0x00],
+                vec![jmp_relocation(
+                    RelocationTarget::Import("print".to_owned()),
+                    0,
+                )],
+                vec![],
+            ),
+            object(
+                vec![0x4C, 0x00, 0x00, 0x60],
+                vec![jmp_relocation(
+                    RelocationTarget::Segment("CODE".to_owned()),
+                    3,
+                )],
+                vec![symbol("print", 3)],
+            ),
+        ];
+        let result = link(&config(), &objects);
+        assert_eq!(
+            Ok(vec![
+                Segment {
+                    name: "ZEROPAGE".to_owned(),
+                    start: 0x0000,
+                    bytes: vec![]
+                },
+                Segment {
+                    name: "CODE".to_owned(),
+                    start: 0x8000,
+                    bytes: vec![0x4C, 0x06, 0x80, 0x4C, 0x06, 0x80, 0x60]
+                },
+            ]),
+            result
+        )
+    }
+
+    #[test]
+    fn link_overflow() {
+        let objects = vec![
+            object(vec![0x60; 5], vec![], vec![]),
+            object(vec![0x60; 4], vec![], vec![]),
+        ];
+        let result = link(&config(), &objects);
+        assert_eq!(
+            Err(Error::SegmentOverflow {
+                segment: "CODE".to_owned(),
+                size: 8,
+                needed: 9
+            }),
+            result
+        )
+    }
+
+    #[test]
+    fn link_unknown_relocation_segment() {
+        let objects = vec![object(
+            vec![0x4C, 0x00, 0x00],
+            vec![jmp_relocation(
+                RelocationTarget::Segment("DATA".to_owned()),
+                0,
+            )],
+            vec![],
+        )];
+        let result = link(&config(), &objects);
+        assert_eq!(Err(Error::UnknownSegment("DATA".to_owned())), result)
+    }
+
+    #[test]
+    fn link_relocation_out_of_bounds() {
+        let objects = vec![object(
+            vec![0x4C],
+            vec![Relocation {
+                offset: 0,
+                ..jmp_relocation(RelocationTarget::Segment("CODE".to_owned()), 0)
+            }],
+            vec![],
+        )];
+        let result = link(&config(), &objects);
+        assert_eq!(
+            Err(Error::InvalidObject {
+                module: 0,
+                source: object::Error::OutOfBounds("relocation", "CODE".to_owned())
+            }),
+            result
+        )
+    }
+
+    #[test]
+    fn link_unresolved_import() {
+        let objects = vec![object(
+            vec![0x4C, 0x00, 0x00],
+            vec![jmp_relocation(
+                RelocationTarget::Import("print".to_owned()),
+                0,
+            )],
+            vec![],
+        )];
+        let result = link(&config(), &objects);
+        assert_eq!(
+            Err(Error::UnresolvedImport("print".to_owned(), location(0))),
+            result
+        )
+    }
+
+    #[test]
+    fn link_duplicate_export() {
+        let objects = vec![
+            object(
+                vec![0x4C, 0x00, 0x00],
+                vec![jmp_relocation(
+                    RelocationTarget::Import("print".to_owned()),
+                    0,
+                )],
+                vec![],
+            ),
+            object(vec![0x60], vec![], vec![symbol("print", 0)]),
+            object(vec![0x60], vec![], vec![symbol("print", 0)]),
+        ];
+        let result = link(&config(), &objects);
+        assert_eq!(Err(Error::DuplicateExport("print".to_owned())), result)
+    }
+
+    #[test]
+    fn link_private_symbol() {
+        let mut print = symbol("print", 0);
+        print.exported = false;
+        let objects = vec![
+            object(
+                vec![0x4C, 0x00, 0x00],
+                vec![jmp_relocation(
+                    RelocationTarget::Import("print".to_owned()),
+                    0,
+                )],
+                vec![],
+            ),
+            object(vec![0x60], vec![], vec![print]),
+        ];
+        let result = link(&config(), &objects);
+        assert_eq!(
+            Err(Error::UnresolvedImport("print".to_owned(), location(0))),
+            result
+        )
+    }
+
+    #[test]
+    fn link_zero_page_out_of_range() {
+        let relocation = Relocation {
+            offset: 1,
+            kind: RelocationKind::ZeroPage,
+            target: RelocationTarget::Import("ptr".to_owned()),
+            addend: 0,
+            source_start: 6,
+            source_end: 9,
+        };
+        let objects = vec![
+            object(vec![0x64, 0x00], vec![relocation], vec![]),
+            object(vec![0x60], vec![], vec![symbol("ptr", 0)]),
+        ];
+        let result = link(&config(), &objects);
+        assert_eq!(
+            Err(Error::ZeroPageOutOfRange {
+                target: "ptr".to_owned(),
+                value: 0x8002,
+                location: Location {
+                    module: 0,
+                    span: Span::new(6, 9)
+                },
+            }),
+            result
+        )
+    }
+
+    fn import_relocation(name: &str) -> Relocation {
+        jmp_relocation(RelocationTarget::Import(name.to_owned()), 0)
+    }
+
+    #[test]
+    fn link_pulls_needed_library_modules() {
+        let mut library = Archive::new();
+        library.add(
+            "unused.o",
+            object(
+                vec![0x4C, 0x00, 0x00],
+                vec![import_relocation("missing")],
+                vec![symbol("unused", 0)],
+            ),
+        );
+        library.add("div.o", object(vec![0x60], vec![], vec![symbol("div", 0)]));
+        library.add(
+            "print.o",
+            object(
+                vec![0x4C, 0x00, 0x00],
+                vec![import_relocation("div")],
+                vec![symbol("print", 0)],
+            ),
+        );
+        let objects = vec![object(
+            vec![0x4C, 0x00, 0x00],
+            vec![import_relocation("print")],
+            vec![],
+        )];
+        let result = link_with_libraries(&config(), &objects, &[library]).unwrap();
+        assert_eq!(
+            vec![0x4C, 0x03, 0x80, 0x4C, 0x06, 0x80, 0x60],
+            result[1].bytes
+        )
+    }
+
+    #[test]
+    fn link_library_order() {
+        let mut first = Archive::new();
+        first.add(
+            "print.o",
+            object(
+                vec![0x4C, 0x00, 0x00],
+                vec![import_relocation("div")],
+                vec![symbol("print", 0)],
+            ),
+        );
+        let mut second = Archive::new();
+        second.add("div.o", object(vec![0x60], vec![], vec![symbol("div", 0)]));
+        let objects = vec![object(
+            vec![0x4C, 0x00, 0x00],
+            vec![import_relocation("print")],
+            vec![],
+        )];
+
+        let result = link_with_libraries(&config(), &objects, &[first.clone(), second.clone()]);
+        assert!(result.is_ok());
+        let result = link_with_libraries(&config(), &objects, &[second, first]);
+        assert_eq!(
+            Err(Error::UnresolvedImport("div".to_owned(), location(1))),
+            result
+        )
+    }
+}
@@ -0,0 +1,136 @@
+//! Warnings about code that assembles but is probably not what was meant.
+//!
+//! Every lint warns by default. Levels can be changed through [`Lints`] or in the source with
+//! `.warning off unused_label`, which applies from that line on. `.warning off` without a lint
+//! name applies to all of them.
+
+use std::collections::HashMap;
+
+use crate::diagnostic::{Diagnostic, Severity, Span};
+use crate::linker::FailedCheck;
+use crate::object::CheckKind;
+
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    Hash,
+    strum_macros::EnumString,
+    strum_macros::Display,
+    strum_macros::EnumIter,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum Lint {
+    /// A label that nothing refers to.
+    UnusedLabel,
+    /// A label with the name of an instruction.
+    MnemonicLabel,
+    /// Absolute addressing for a label that turned out to be on the zero page.
+    ImplicitAbsolute,
+    /// A `.org` to the address the program counter is already at.
+    RedundantOrg,
+    /// An indirect `JMP` through a pointer at `$xxFF` on the NMOS 6502, which reads the high byte
+    /// of the target from `$xx00`. Checked when linking.
+    JmpIndirectPageWrap,
+    /// A branch that crosses a page inside a `.pagecheck` region, or an indexed read there that
+    /// can cross one with the index values the region allows. Checked when linking.
+    PageCross,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, strum_macros::EnumString)]
+pub enum Level {
+    #[strum(serialize = "allow", serialize = "off")]
+    Allow,
+    #[strum(serialize = "warn", serialize = "on")]
+    Warn,
+    /// The warning is reported as an error.
+    #[strum(serialize = "deny")]
+    Deny,
+}
+
+/// The level of each lint.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Lints(HashMap<Lint, Level>);
+
+impl Lints {
+    pub fn level(&self, lint: Lint) -> Level {
+        self.0.get(&lint).copied().unwrap_or(Level::Warn)
+    }
+
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.0.insert(lint, level);
+    }
+
+    pub fn set_all(&mut self, level: Level) {
+        use strum::IntoEnumIterator;
+        for lint in Lint::iter() {
+            self.set(lint, level);
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Warning {
+    pub lint: Lint,
+    pub level: Level,
+    pub message: String,
+    pub span: Span,
+    /// The index of the module `span` is in, among the linked modules.
+    pub module: usize,
+}
+
+impl Warning {
+    /// The warning for a check that failed when linking.
+    pub fn from_failed_check(failed: &FailedCheck) -> Self {
+        let check = &failed.check;
+        let lint = match check.kind {
+            CheckKind::JmpIndirectPageWrap => Lint::JmpIndirectPageWrap,
+            CheckKind::BranchPageCross | CheckKind::IndexedPageCross { .. } => Lint::PageCross,
+        };
+        Warning {
+            lint,
+            level: if check.deny { Level::Deny } else { Level::Warn },
+            message: failed.message(),
+            span: Span::new(check.source_start as usize, check.source_end as usize),
+            module: failed.module,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            message: format!("{} [{}]", self.message, self.lint),
+            span: self.span,
+            severity: match self.level {
+                Level::Deny => Severity::Error,
+                Level::Allow | Level::Warn => Severity::Warning,
+            },
+            module: self.module,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn names() {
+        assert_eq!(Ok(Lint::UnusedLabel), Lint::from_str("unused_label"));
+        assert_eq!("implicit_absolute", Lint::ImplicitAbsolute.to_string());
+        assert_eq!(Ok(Level::Allow), Level::from_str("off"));
+        assert_eq!(Ok(Level::Deny), Level::from_str("deny"))
+    }
+
+    #[test]
+    fn levels() {
+        let mut lints = Lints::default();
+        assert_eq!(Level::Warn, lints.level(Lint::RedundantOrg));
+        lints.set_all(Level::Allow);
+        lints.set(Lint::RedundantOrg, Level::Deny);
+        assert_eq!(Level::Deny, lints.level(Lint::RedundantOrg));
+        assert_eq!(Level::Allow, lints.level(Lint::UnusedLabel))
+    }
+}
@@ -0,0 +1,192 @@
+//! Listings: every source line next to the address and bytes it assembled to, and the cycles
+//! of instructions.
+
+use std::fmt::Write;
+
+use crate::linker::{Layout, PlacedLine};
+
+/// Which columns to show and how to split the listing into pages.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    pub line_numbers: bool,
+    pub addresses: bool,
+    /// Bytes shown per row, longer lines continue on the next rows. Zero hides the bytes.
+    pub bytes_per_row: usize,
+    /// Cycles per instruction, as a range like `2-4` when they depend on whether a branch is
+    /// taken or a page is crossed.
+    pub cycles: bool,
+    pub source: bool,
+    /// Rows per page including the two header rows. Zero disables paging and headers.
+    pub page_length: usize,
+    pub title: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            line_numbers: true,
+            addresses: true,
+            bytes_per_row: 4,
+            cycles: true,
+            source: true,
+            page_length: 0,
+            title: String::new(),
+        }
+    }
+}
+
+const HEADER_ROWS: usize = 2;
+
+/// Lists `source`, the source of the module at index `module` of the modules that were linked
+/// into `layout`.
+pub fn write(source: &str, module: usize, layout: &Layout, options: &Options) -> String {
+    let lines: Vec<&PlacedLine> = layout.lines.iter().filter(|l| l.module == module).collect();
+    let mut rows = vec![];
+    let mut start = 0;
+    for (number, text) in source.lines().enumerate() {
+        let end = start + text.len();
+        let placed = lines
+            .iter()
+            .find(|l| (start..=end).contains(&(l.source_offset as usize)));
+        start = end + 1;
+        let (address, bytes, cycles) = match placed {
+            Some(line) => (Some(line.address), placed_bytes(layout, line), line.cycles),
+            None => (None, &[][..], None),
+        };
+
+        let mut chunks: Vec<&[u8]> = match options.bytes_per_row {
+            0 => vec![],
+            n => bytes.chunks(n).collect(),
+        };
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let mut row = String::new();
+            if options.line_numbers {
+                match idx {
+                    0 => write!(row, "{:>5}  ", number + 1).unwrap(),
+                    _ => row.push_str(&" ".repeat(7)),
+                }
+            }
+            if options.addresses {
+                match address {
+                    Some(address) => {
+                        let address = address.wrapping_add((idx * options.bytes_per_row) as u16);
+                        write!(row, "{:04X}  ", address).unwrap()
+                    }
+                    None => row.push_str(&" ".repeat(6)),
+                }
+            }
+            if options.bytes_per_row > 0 {
+                for byte in chunk.iter() {
+                    write!(row, "{:02X} ", byte).unwrap();
+                }
+                row.push_str(&" ".repeat(3 * (options.bytes_per_row - chunk.len()) + 1));
+            }
+            if options.cycles {
+                let text = match cycles.filter(|_| idx == 0) {
+                    Some(c) if c.best() == c.worst() => c.best().to_string(),
+                    Some(c) => format!("{}-{}", c.best(), c.worst()),
+                    None => String::new(),
+                };
+                write!(row, "{:<5}", text).unwrap();
+            }
+            if options.source && idx == 0 {
+                row.push_str(text);
+            }
+            rows.push(row.trim_end().to_owned());
+        }
+    }
+
+    let mut out = String::new();
+    let page_rows = options.page_length.saturating_sub(HEADER_ROWS).max(1);
+    for (page, rows) in rows.chunks(page_rows).enumerate() {
+        if options.page_length > 0 {
+            if page > 0 {
+                out.push('\x0C');
+            }
+            let header = format!("{}  Page {}", options.title, page + 1);
+            writeln!(out, "{}\n", header.trim_start()).unwrap();
+        }
+        for row in rows {
+            writeln!(out, "{}", row).unwrap();
+        }
+    }
+    out
+}
+
+fn placed_bytes<'a>(layout: &'a Layout, line: &PlacedLine) -> &'a [u8] {
+    layout
+        .segments
+        .iter()
+        .find(|s| s.name == line.segment)
+        .and_then(|s| {
+            let start = line.address.wrapping_sub(s.start) as usize;
+            s.bytes.get(start..start + line.size as usize)
+        })
+        .unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_object;
+    use crate::linker::{self, LinkerConfig};
+
+    const SOURCE: &str = "  STZ $0300\n\nloop:\n  JMP loop\n  RTS\n";
+
+    fn layout(source: &str) -> Layout {
+        let config: LinkerConfig = "CODE: start = $8000, size = $1000;".parse().unwrap();
+        linker::layout(&config, &[assemble_object(source).unwrap()], &[]).unwrap()
+    }
+
+    #[test]
+    fn write_success() {
+        let result = write(SOURCE, 0, &layout(SOURCE), &Options::default());
+        let expected = "    1  8000  9C 00 03     4      STZ $0300\n    2\n    3  8003                    loop:\n    4  8003  4C 03 80     3      JMP loop\n    5  8006  60           6      RTS\n";
+        assert_eq!(expected, result)
+    }
+
+    #[test]
+    fn cycle_ranges() {
+        let source = "loop:\n  LDA $0300,X\n  BNE loop\n";
+        let options = Options {
+            line_numbers: false,
+            addresses: false,
+            bytes_per_row: 0,
+            ..Options::default()
+        };
+        let result = write(source, 0, &layout(source), &options);
+        let expected = "     loop:\n4-5    LDA $0300,X\n2-4    BNE loop\n";
+        assert_eq!(expected, result)
+    }
+
+    #[test]
+    fn continuation_rows() {
+        let options = Options {
+            line_numbers: false,
+            bytes_per_row: 2,
+            cycles: false,
+            ..Options::default()
+        };
+        let result = write(SOURCE, 0, &layout(SOURCE), &options);
+        let expected = "8000  9C 00    STZ $0300\n8002  03\n\n8003         loop:\n8003  4C 03    JMP loop\n8005  80\n8006  60       RTS\n";
+        assert_eq!(expected, result)
+    }
+
+    #[test]
+    fn page_headers() {
+        let options = Options {
+            addresses: false,
+            bytes_per_row: 0,
+            cycles: false,
+            page_length: 5,
+            title: "test.s".to_owned(),
+            ..Options::default()
+        };
+        let result = write(SOURCE, 0, &layout(SOURCE), &options);
+        let expected = "test.s  Page 1\n\n    1    STZ $0300\n    2\n    3  loop:\n\x0Ctest.s  Page 2\n\n    4    JMP loop\n    5    RTS\n";
+        assert_eq!(expected, result)
+    }
+}
@@ -0,0 +1,649 @@
+//! Relocatable object files, the output of assembling a single module.
+//!
+//! The serialized form is little-endian throughout:
+//!
+//! ```text
+//! magic    "SFTO"
+//! version  u16
+//! segments u16 count, each: name, u8 has org, u16 org, u16 byte count, bytes,
+//!          u16 count, relocations
+//! symbols  u16 count, each: name, segment name, u16 offset, u8 exported
+//! imports  u16 count, each: name, u8 address size, u32 source start, u32 source end
+//! lines    u16 count, each: u32 source offset, segment name, u16 offset, u16 size,
+//!          u8 has cycles, u8 base, u8 page cross and u8 taken cycles
+//! entry    u8 present, symbol name if present
+//! inits    u16 count, each: symbol name
+//! checks   u16 count, each: u8 kind, u16 index count for indexed reads, segment name,
+//!          u16 offset, u32 source start, u32 source end, u8 deny
+//! ```
+//!
+//! Strings are stored as a u16 length followed by UTF-8, relocations as
+//! u16 offset, u8 kind, u8 target tag, target name, u16 addend, u32 source start and
+//! u32 source end.
+
+use std::convert::TryFrom;
+
+use crate::cpu::Cycles;
+
+pub const MAGIC: [u8; 4] = *b"SFTO";
+pub const VERSION: u16 = 12;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("not an object file")]
+    BadMagic,
+    #[error("unsupported object file version {0}")]
+    UnsupportedVersion(u16),
+    #[error("object file is truncated")]
+    Truncated,
+    #[error("object file contains a string that is not UTF-8")]
+    InvalidString,
+    #[error("object file contains unknown {0} tag {1}")]
+    InvalidTag(&'static str, u8),
+    #[error("object file has {0} bytes of trailing data")]
+    TrailingData(usize),
+    #[error("{0} does not fit in an object file")]
+    TooLarge(&'static str),
+    #[error("object file has more than one fragment of segment {0}")]
+    DuplicateSegment(String),
+    #[error("object file has a {0} outside its fragment of segment {1}")]
+    OutOfBounds(&'static str, String),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Object {
+    pub segments: Vec<ObjectSegment>,
+    pub symbols: Vec<Symbol>,
+    pub imports: Vec<Import>,
+    pub lines: Vec<Line>,
+    /// The symbol execution starts at, set with `.entry`.
+    pub entry: Option<String>,
+    /// Symbols to call while loading, set with `.init`, in order.
+    pub inits: Vec<String>,
+    pub checks: Vec<Check>,
+}
+
+/// This module's contribution to a segment. The linker places the fragments of
+/// all modules one after another.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ObjectSegment {
+    pub name: String,
+    /// The fixed start address of a fragment started with `.org`. These are not part of a
+    /// configured segment, the linker places them as a segment of their own.
+    pub org: Option<u16>,
+    pub bytes: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// A label definition, relative to the start of this module's fragment of `segment`.
+/// Only exported symbols can be imported by other modules.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub segment: String,
+    pub offset: u16,
+    pub exported: bool,
+}
+
+/// A symbol this module needs from another module's exports.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Import {
+    pub name: String,
+    pub size: AddressSize,
+    /// The byte range of the source that imports it.
+    pub source_start: u32,
+    pub source_end: u32,
+}
+
+/// Where the element starting at byte `source_offset` of the module's source was placed,
+/// relative to this module's fragment of `segment`, and the cycles it takes if it is an
+/// instruction.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Line {
+    pub source_offset: u32,
+    pub segment: String,
+    pub offset: u16,
+    pub size: u16,
+    pub cycles: Option<Cycles>,
+}
+
+/// Something about the instruction starting at `offset` in this module's fragment of `segment`
+/// that can only be checked at its final address. The linker reports the checks that fail.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Check {
+    pub kind: CheckKind,
+    pub segment: String,
+    pub offset: u16,
+    /// The byte range of the source the check is about.
+    pub source_start: u32,
+    pub source_end: u32,
+    /// Whether a failure is an error rather than a warning.
+    pub deny: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CheckKind {
+    /// An indirect `JMP` through a pointer at `$xxFF`, whose high byte the NMOS 6502 reads from
+    /// `$xx00`.
+    JmpIndirectPageWrap,
+    /// A branch whose target is in a different page than the next instruction, so taking it
+    /// costs an extra cycle.
+    BranchPageCross,
+    /// An indexed read where adding one of the first `indexes` index values to the base address
+    /// crosses into the next page, which costs an extra cycle.
+    IndexedPageCross { indexes: u16 },
+}
+
+impl CheckKind {
+    fn write(self, writer: &mut Writer) {
+        match self {
+            CheckKind::JmpIndirectPageWrap => writer.0.push(0),
+            CheckKind::BranchPageCross => writer.0.push(1),
+            CheckKind::IndexedPageCross { indexes } => {
+                writer.0.push(2);
+                writer.u16(indexes);
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, Error> {
+        match reader.u8()? {
+            0 => Ok(CheckKind::JmpIndirectPageWrap),
+            1 => Ok(CheckKind::BranchPageCross),
+            2 => Ok(CheckKind::IndexedPageCross {
+                indexes: reader.u16()?,
+            }),
+            other => Err(Error::InvalidTag("check kind", other)),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum AddressSize {
+    ZeroPage,
+    Absolute,
+}
+
+/// A location in a fragment that can only be filled in once the final address
+/// of `target` is known. The value written is that address plus `addend`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Relocation {
+    pub offset: u16,
+    pub kind: RelocationKind,
+    pub target: RelocationTarget,
+    pub addend: u16,
+    /// The byte range of the source that refers to `target`.
+    pub source_start: u32,
+    pub source_end: u32,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum RelocationKind {
+    Word,
+    /// A single byte that must not exceed `$FF`.
+    ZeroPage,
+    /// The low byte of the value, from the `<` operator.
+    Low,
+    /// The high byte of the value, from the `>` operator.
+    High,
+    /// The value as five ASCII digits, right-aligned with spaces, for BASIC `SYS` lines.
+    Decimal,
+    /// A branch offset: the value minus the address after this byte, which must fit in a signed
+    /// byte.
+    Relative,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum RelocationTarget {
+    /// The start of this module's fragment of the named segment.
+    Segment(String),
+    /// A symbol defined in another module.
+    Import(String),
+}
+
+impl RelocationKind {
+    pub fn size(self) -> u16 {
+        match self {
+            RelocationKind::Word => 2,
+            RelocationKind::ZeroPage
+            | RelocationKind::Low
+            | RelocationKind::High
+            | RelocationKind::Relative => 1,
+            RelocationKind::Decimal => 5,
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            RelocationKind::Word => 0,
+            RelocationKind::ZeroPage => 1,
+            RelocationKind::Low => 2,
+            RelocationKind::High => 3,
+            RelocationKind::Decimal => 4,
+            RelocationKind::Relative => 5,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, Error> {
+        match tag {
+            0 => Ok(RelocationKind::Word),
+            1 => Ok(RelocationKind::ZeroPage),
+            2 => Ok(RelocationKind::Low),
+            3 => Ok(RelocationKind::High),
+            4 => Ok(RelocationKind::Decimal),
+            5 => Ok(RelocationKind::Relative),
+            other => Err(Error::InvalidTag("relocation kind", other)),
+        }
+    }
+}
+
+impl AddressSize {
+    fn tag(self) -> u8 {
+        match self {
+            AddressSize::ZeroPage => 0,
+            AddressSize::Absolute => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, Error> {
+        match tag {
+            0 => Ok(AddressSize::ZeroPage),
+            1 => Ok(AddressSize::Absolute),
+            other => Err(Error::InvalidTag("address size", other)),
+        }
+    }
+}
+
+impl Object {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut writer = Writer(MAGIC.to_vec());
+        writer.u16(VERSION);
+        writer.count(self.segments.len(), "segment list")?;
+        for segment in &self.segments {
+            writer.str(&segment.name)?;
+            writer.0.push(segment.org.is_some() as u8);
+            writer.u16(segment.org.unwrap_or(0));
+            writer.count(segment.bytes.len(), "segment")?;
+            writer.0.extend(&segment.bytes);
+            writer.count(segment.relocations.len(), "relocation list")?;
+            for relocation in &segment.relocations {
+                writer.u16(relocation.offset);
+                writer.0.push(relocation.kind.tag());
+                match &relocation.target {
+                    RelocationTarget::Segment(name) => {
+                        writer.0.push(0);
+                        writer.str(name)?;
+                    }
+                    RelocationTarget::Import(name) => {
+                        writer.0.push(1);
+                        writer.str(name)?;
+                    }
+                }
+                writer.u16(relocation.addend);
+                writer.u32(relocation.source_start);
+                writer.u32(relocation.source_end);
+            }
+        }
+        writer.count(self.symbols.len(), "symbol list")?;
+        for symbol in &self.symbols {
+            writer.str(&symbol.name)?;
+            writer.str(&symbol.segment)?;
+            writer.u16(symbol.offset);
+            writer.0.push(symbol.exported as u8);
+        }
+        writer.count(self.imports.len(), "import list")?;
+        for import in &self.imports {
+            writer.str(&import.name)?;
+            writer.0.push(import.size.tag());
+            writer.u32(import.source_start);
+            writer.u32(import.source_end);
+        }
+        writer.count(self.lines.len(), "line list")?;
+        for line in &self.lines {
+            writer.u32(line.source_offset);
+            writer.str(&line.segment)?;
+            writer.u16(line.offset);
+            writer.u16(line.size);
+            let cycles = line.cycles.unwrap_or_default();
+            writer.0.push(line.cycles.is_some() as u8);
+            writer
+                .0
+                .extend(&[cycles.base, cycles.page_cross, cycles.taken]);
+        }
+        writer.0.push(self.entry.is_some() as u8);
+        if let Some(entry) = &self.entry {
+            writer.str(entry)?;
+        }
+        writer.count(self.inits.len(), "init list")?;
+        for init in &self.inits {
+            writer.str(init)?;
+        }
+        writer.count(self.checks.len(), "check list")?;
+        for check in &self.checks {
+            check.kind.write(&mut writer);
+            writer.str(&check.segment)?;
+            writer.u16(check.offset);
+            writer.u32(check.source_start);
+            writer.u32(check.source_end);
+            writer.0.push(check.deny as u8);
+        }
+        Ok(writer.0)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let segments = reader.list(|reader| {
+            let name = reader.string()?;
+            let has_org = reader.bool()?;
+            let org = reader.u16()?;
+            let len = reader.u16()? as usize;
+            let bytes = reader.take(len)?.to_vec();
+            let relocations = reader.list(|reader| {
+                let offset = reader.u16()?;
+                let kind = RelocationKind::from_tag(reader.u8()?)?;
+                let target = match reader.u8()? {
+                    0 => RelocationTarget::Segment(reader.string()?),
+                    1 => RelocationTarget::Import(reader.string()?),
+                    other => return Err(Error::InvalidTag("relocation target", other)),
+                };
+                Ok(Relocation {
+                    offset,
+                    kind,
+                    target,
+                    addend: reader.u16()?,
+                    source_start: reader.u32()?,
+                    source_end: reader.u32()?,
+                })
+            })?;
+            Ok(ObjectSegment {
+                name,
+                org: if has_org { Some(org) } else { None },
+                bytes,
+                relocations,
+            })
+        })?;
+        let symbols = reader.list(|reader| {
+            Ok(Symbol {
+                name: reader.string()?,
+                segment: reader.string()?,
+                offset: reader.u16()?,
+                exported: reader.bool()?,
+            })
+        })?;
+        let imports = reader.list(|reader| {
+            Ok(Import {
+                name: reader.string()?,
+                size: AddressSize::from_tag(reader.u8()?)?,
+                source_start: reader.u32()?,
+                source_end: reader.u32()?,
+            })
+        })?;
+        let lines = reader.list(|reader| {
+            let source_offset = reader.u32()?;
+            let segment = reader.string()?;
+            let offset = reader.u16()?;
+            let size = reader.u16()?;
+            let has_cycles = reader.bool()?;
+            let cycles = Cycles {
+                base: reader.u8()?,
+                page_cross: reader.u8()?,
+                taken: reader.u8()?,
+            };
+            Ok(Line {
+                source_offset,
+                segment,
+                offset,
+                size,
+                cycles: if has_cycles { Some(cycles) } else { None },
+            })
+        })?;
+        let entry = match reader.bool()? {
+            true => Some(reader.string()?),
+            false => None,
+        };
+        let inits = reader.list(|reader| reader.string())?;
+        let checks = reader.list(|reader| {
+            Ok(Check {
+                kind: CheckKind::read(reader)?,
+                segment: reader.string()?,
+                offset: reader.u16()?,
+                source_start: reader.u32()?,
+                source_end: reader.u32()?,
+                deny: reader.bool()?,
+            })
+        })?;
+        if !reader.0.is_empty() {
+            return Err(Error::TrailingData(reader.0.len()));
+        }
+        let object = Object {
+            segments,
+            symbols,
+            imports,
+            lines,
+            entry,
+            inits,
+            checks,
+        };
+        object.validate()?;
+        Ok(object)
+    }
+
+    /// Checks that relocations and checks are inside the fragment they belong to, so the linker
+    /// can patch them. Segment names are checked against the configuration when linking.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        for (idx, segment) in self.segments.iter().enumerate() {
+            if self.segments[..idx].iter().any(|s| s.name == segment.name) {
+                return Err(Error::DuplicateSegment(segment.name.clone()));
+            }
+            let len = segment.bytes.len();
+            for relocation in &segment.relocations {
+                if relocation.offset as usize + relocation.kind.size() as usize > len {
+                    return Err(Error::OutOfBounds("relocation", segment.name.clone()));
+                }
+            }
+        }
+        for check in &self.checks {
+            let fragment = self.segments.iter().find(|s| s.name == check.segment);
+            if fragment.is_none_or(|f| check.offset as usize >= f.bytes.len()) {
+                return Err(Error::OutOfBounds("check", check.segment.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u16(&mut self, val: u16) {
+        self.0.extend(&val.to_le_bytes());
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.0.extend(&val.to_le_bytes());
+    }
+
+    fn count(&mut self, len: usize, what: &'static str) -> Result<(), Error> {
+        let len = u16::try_from(len).map_err(|_| Error::TooLarge(what))?;
+        self.u16(len);
+        Ok(())
+    }
+
+    fn str(&mut self, s: &str) -> Result<(), Error> {
+        self.count(s.len(), "name")?;
+        self.0.extend(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Reads the little-endian encoding shared with library archives. The slice is what is left.
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Error> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(Error::InvalidTag("boolean", other)),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Error> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Error> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, Error> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidString)
+    }
+
+    pub(crate) fn list<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let len = self.u16()?;
+        (0..len).map(|_| f(self)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object() -> Object {
+        Object {
+            segments: vec![ObjectSegment {
+                name: "CODE".to_owned(),
+                org: None,
+                bytes: vec![0x4C, 0x00, 0x00, 0x4C, 0x00, 0x00],
+                relocations: vec![
+                    Relocation {
+                        offset: 1,
+                        kind: RelocationKind::Word,
+                        target: RelocationTarget::Segment("CODE".to_owned()),
+                        addend: 3,
+                        source_start: 20,
+                        source_end: 24,
+                    },
+                    Relocation {
+                        offset: 4,
+                        kind: RelocationKind::Word,
+                        target: RelocationTarget::Import("print".to_owned()),
+                        addend: 0,
+                        source_start: 70000,
+                        source_end: 70005,
+                    },
+                ],
+            }],
+            symbols: vec![Symbol {
+                name: "loop".to_owned(),
+                segment: "CODE".to_owned(),
+                offset: 3,
+                exported: true,
+            }],
+            imports: vec![Import {
+                name: "print".to_owned(),
+                size: AddressSize::Absolute,
+                source_start: 9,
+                source_end: 14,
+            }],
+            lines: vec![Line {
+                source_offset: 70000,
+                segment: "CODE".to_owned(),
+                offset: 3,
+                size: 3,
+                cycles: Some(Cycles {
+                    base: 2,
+                    page_cross: 1,
+                    taken: 1,
+                }),
+            }],
+            entry: Some("loop".to_owned()),
+            inits: vec!["loop".to_owned()],
+            checks: vec![Check {
+                kind: CheckKind::JmpIndirectPageWrap,
+                segment: "CODE".to_owned(),
+                offset: 3,
+                source_start: 70000,
+                source_end: 70010,
+                deny: true,
+            }],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = object().to_bytes().unwrap();
+        assert_eq!(Ok(object()), Object::from_bytes(&bytes))
+    }
+
+    #[test]
+    fn bad_magic() {
+        let result = Object::from_bytes(b"SFTX\x01\x00");
+        assert_eq!(Err(Error::BadMagic), result)
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = object().to_bytes().unwrap();
+        bytes[4] = 0xFF;
+        assert_eq!(
+            Err(Error::UnsupportedVersion(0x00FF)),
+            Object::from_bytes(&bytes)
+        )
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut bad = object();
+        bad.segments[0].relocations[1].offset = 5;
+        let result = Object::from_bytes(&bad.to_bytes().unwrap());
+        assert_eq!(
+            Err(Error::OutOfBounds("relocation", "CODE".to_owned())),
+            result
+        );
+
+        let mut bad = object();
+        bad.checks[0].segment = "DATA".to_owned();
+        let result = Object::from_bytes(&bad.to_bytes().unwrap());
+        assert_eq!(Err(Error::OutOfBounds("check", "DATA".to_owned())), result);
+
+        let mut bad = object();
+        bad.segments.push(bad.segments[0].clone());
+        let result = Object::from_bytes(&bad.to_bytes().unwrap());
+        assert_eq!(Err(Error::DuplicateSegment("CODE".to_owned())), result)
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = object().to_bytes().unwrap();
+        let result = Object::from_bytes(&bytes[..bytes.len() - 1]);
+        assert_eq!(Err(Error::Truncated), result)
+    }
+}
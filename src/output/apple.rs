@@ -0,0 +1,186 @@
+//! Apple II binaries: DOS 3.3 `B` files as loaded by `BLOAD`, and AppleSingle files with ProDOS
+//! file info.
+//!
+//! Both hold a single block of memory. It starts at the first `.org`, or at the lowest segment
+//! if there is none, and runs to the end of the highest segment with the gaps zero filled.
+
+use std::convert::TryFrom;
+
+use crate::linker::Layout;
+
+const APPLE_SINGLE_MAGIC: u32 = 0x0005_1600;
+const APPLE_SINGLE_VERSION: u32 = 0x0002_0000;
+const ENTRY_DATA_FORK: u32 = 1;
+const ENTRY_REAL_NAME: u32 = 3;
+const ENTRY_PRODOS_FILE_INFO: u32 = 11;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("there are no bytes to write")]
+    Empty,
+    #[error("segment {0} starts before the load address")]
+    BeforeLoadAddress(String),
+    #[error("{0} bytes do not fit in a DOS 3.3 binary")]
+    TooLarge(usize),
+}
+
+/// ProDOS file attributes for AppleSingle files. The aux type is the load address.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    /// Stored as the real name entry when not empty.
+    pub name: String,
+    pub file_type: u16,
+    pub access: u16,
+}
+
+impl Default for Options {
+    /// A `BIN` file that can be read, written, renamed and deleted.
+    fn default() -> Self {
+        Options {
+            name: String::new(),
+            file_type: 0x06,
+            access: 0xC3,
+        }
+    }
+}
+
+/// The load address and the bytes from there on.
+fn image(layout: &Layout) -> Result<(u16, Vec<u8>), Error> {
+    let segments: Vec<_> = layout
+        .segments
+        .iter()
+        .filter(|s| !s.bytes.is_empty())
+        .collect();
+    let lowest = segments.iter().map(|s| s.start).min().ok_or(Error::Empty)?;
+    let start = layout.orgs.first().copied().unwrap_or(lowest);
+    let end = segments
+        .iter()
+        .map(|s| s.start as usize + s.bytes.len())
+        .max()
+        .ok_or(Error::Empty)?;
+
+    let mut bytes = vec![0; end.saturating_sub(start as usize)];
+    for segment in segments {
+        if segment.start < start {
+            return Err(Error::BeforeLoadAddress(segment.name.clone()));
+        }
+        let offset = (segment.start - start) as usize;
+        bytes[offset..offset + segment.bytes.len()].copy_from_slice(&segment.bytes);
+    }
+    Ok((start, bytes))
+}
+
+/// A DOS 3.3 binary: load address and length, followed by the bytes. The length is 16 bits, so
+/// an image of the whole address space does not fit.
+pub fn dos33_binary(layout: &Layout) -> Result<Vec<u8>, Error> {
+    let (start, bytes) = image(layout)?;
+    let len = u16::try_from(bytes.len()).map_err(|_| Error::TooLarge(bytes.len()))?;
+    let mut out = start.to_le_bytes().to_vec();
+    out.extend(&len.to_le_bytes());
+    out.extend(bytes);
+    Ok(out)
+}
+
+pub fn apple_single(layout: &Layout, options: &Options) -> Result<Vec<u8>, Error> {
+    let (start, bytes) = image(layout)?;
+    let mut file_info = options.access.to_be_bytes().to_vec();
+    file_info.extend(&options.file_type.to_be_bytes());
+    file_info.extend(&(start as u32).to_be_bytes());
+
+    let mut entries = vec![];
+    if !options.name.is_empty() {
+        entries.push((ENTRY_REAL_NAME, options.name.as_bytes()));
+    }
+    entries.push((ENTRY_PRODOS_FILE_INFO, &file_info[..]));
+    entries.push((ENTRY_DATA_FORK, &bytes[..]));
+
+    let mut out = APPLE_SINGLE_MAGIC.to_be_bytes().to_vec();
+    out.extend(&APPLE_SINGLE_VERSION.to_be_bytes());
+    out.extend(&[0; 16]);
+    out.extend(&(entries.len() as u16).to_be_bytes());
+    let mut offset = out.len() + 12 * entries.len();
+    for (id, data) in &entries {
+        out.extend(&id.to_be_bytes());
+        out.extend(&(offset as u32).to_be_bytes());
+        out.extend(&(data.len() as u32).to_be_bytes());
+        offset += data.len();
+    }
+    for (_, data) in entries {
+        out.extend(data);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::{LinkerConfig, Segment};
+
+    fn layout() -> Layout {
+        let config: LinkerConfig = "CODE: start = $0800, size = $1000;".parse().unwrap();
+        assemble_layout(
+            "  .org $0300\nstart:\n  JMP start\n  .org $0304\n  RTS\n",
+            &config,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn dos33_binary_success() {
+        assert_eq!(
+            Ok(vec![0x00, 0x03, 0x05, 0x00, 0x4C, 0x00, 0x03, 0x00, 0x60]),
+            dos33_binary(&layout())
+        )
+    }
+
+    #[test]
+    fn apple_single_success() {
+        let options = Options {
+            name: "HELLO".to_owned(),
+            ..Options::default()
+        };
+        let result = apple_single(&layout(), &options).unwrap();
+        let mut expected = vec![0x00, 0x05, 0x16, 0x00, 0x00, 0x02, 0x00, 0x00];
+        expected.extend(&[0; 16]);
+        expected.extend(&[0x00, 0x03]);
+        expected.extend(&[0, 0, 0, 3, 0, 0, 0, 62, 0, 0, 0, 5]);
+        expected.extend(&[0, 0, 0, 11, 0, 0, 0, 67, 0, 0, 0, 8]);
+        expected.extend(&[0, 0, 0, 1, 0, 0, 0, 75, 0, 0, 0, 5]);
+        expected.extend(b"HELLO");
+        expected.extend(&[0x00, 0xC3, 0x00, 0x06, 0x00, 0x00, 0x03, 0x00]);
+        expected.extend(&[0x4C, 0x00, 0x03, 0x00, 0x60]);
+        assert_eq!(expected, result)
+    }
+
+    #[test]
+    fn first_org_is_load_address() {
+        let config: LinkerConfig = "CODE: start = $0800, size = $1000;".parse().unwrap();
+        let layout =
+            assemble_layout("  .org $0900\n  RTS\n  .org $0300\n  RTS\n", &config).unwrap();
+        assert_eq!(
+            Err(Error::BeforeLoadAddress("ORG_0300".to_owned())),
+            dos33_binary(&layout)
+        )
+    }
+
+    #[test]
+    fn dos33_binary_too_large() {
+        let layout = Layout {
+            segments: vec![Segment {
+                name: "CODE".to_owned(),
+                start: 0x0000,
+                bytes: vec![0xEA; 0x10000],
+            }],
+            symbols: vec![],
+            relocations: vec![],
+            unresolved: vec![],
+            lines: vec![],
+            entry: None,
+            inits: vec![],
+            orgs: vec![],
+            failed_checks: vec![],
+        };
+        assert_eq!(Err(Error::TooLarge(0x10000)), dos33_binary(&layout))
+    }
+}
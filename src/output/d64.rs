@@ -0,0 +1,320 @@
+//! Commodore 1541 disk images (D64), for handing PRG files to emulators.
+//!
+//! Files are stored as PRG files. Their sectors are allocated on the tracks closest to the
+//! directory track first, with the 1541's interleave of 10 sectors.
+
+const TRACKS: u8 = 35;
+const SECTOR_SIZE: usize = 256;
+const DIRECTORY_TRACK: u8 = 18;
+const FILE_INTERLEAVE: u8 = 10;
+const DIRECTORY_INTERLEAVE: u8 = 3;
+const ENTRIES_PER_SECTOR: usize = 8;
+const NAME_LENGTH: usize = 16;
+const PADDING: u8 = 0xA0;
+const FILE_TYPE_PRG: u8 = 0x82;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("name {0:?} is longer than 16 characters or not ASCII")]
+    InvalidName(String),
+    #[error("disk is full")]
+    DiskFull,
+    #[error("directory is full")]
+    DirectoryFull,
+}
+
+/// A disk image that files can be added to one by one.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct D64 {
+    bytes: Vec<u8>,
+    /// Free sectors, by track and sector.
+    free: Vec<Vec<bool>>,
+    directory: Vec<(u8, u8)>,
+}
+
+fn sectors_in_track(track: u8) -> u8 {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+fn padded_name(name: &str) -> Result<[u8; NAME_LENGTH], Error> {
+    if name.len() > NAME_LENGTH || !name.is_ascii() {
+        return Err(Error::InvalidName(name.to_owned()));
+    }
+    let mut padded = [PADDING; NAME_LENGTH];
+    padded[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(padded)
+}
+
+impl D64 {
+    /// A formatted, empty disk. `id` is the two character disk ID.
+    pub fn new(name: &str, id: &str) -> Result<Self, Error> {
+        let name = padded_name(name)?;
+        if id.len() != 2 || !id.is_ascii() {
+            return Err(Error::InvalidName(id.to_owned()));
+        }
+        let size: usize = (1..=TRACKS)
+            .map(|t| sectors_in_track(t) as usize * SECTOR_SIZE)
+            .sum();
+        let mut disk = D64 {
+            bytes: vec![0; size],
+            free: (1..=TRACKS)
+                .map(|t| vec![true; sectors_in_track(t) as usize])
+                .collect(),
+            directory: vec![],
+        };
+        disk.free[DIRECTORY_TRACK as usize - 1][0] = false;
+        disk.free[DIRECTORY_TRACK as usize - 1][1] = false;
+        disk.directory.push((DIRECTORY_TRACK, 1));
+
+        let bam = disk.sector_mut(DIRECTORY_TRACK, 0);
+        bam[0x00] = DIRECTORY_TRACK;
+        bam[0x01] = 1;
+        bam[0x02] = b'A';
+        bam[0x90..0xA0].copy_from_slice(&name);
+        bam[0xA0..0xAB].copy_from_slice(&[PADDING; 11]);
+        bam[0xA2..0xA4].copy_from_slice(id.as_bytes());
+        bam[0xA5..0xA7].copy_from_slice(b"2A");
+        disk.sector_mut(DIRECTORY_TRACK, 1)[1] = 0xFF;
+        disk.write_bam();
+        Ok(disk)
+    }
+
+    fn offset(track: u8, sector: u8) -> usize {
+        let before: usize = (1..track)
+            .map(|t| sectors_in_track(t) as usize * SECTOR_SIZE)
+            .sum();
+        before + sector as usize * SECTOR_SIZE
+    }
+
+    fn sector_mut(&mut self, track: u8, sector: u8) -> &mut [u8] {
+        let offset = Self::offset(track, sector);
+        &mut self.bytes[offset..offset + SECTOR_SIZE]
+    }
+
+    fn write_bam(&mut self) {
+        for track in 1..=TRACKS {
+            let free = &self.free[track as usize - 1];
+            let mut entry = [0u8; 4];
+            entry[0] = free.iter().filter(|f| **f).count() as u8;
+            for (sector, _) in free.iter().enumerate().filter(|(_, f)| **f) {
+                entry[1 + sector / 8] |= 1 << (sector % 8);
+            }
+            let at = 4 * track as usize;
+            self.sector_mut(DIRECTORY_TRACK, 0)[at..at + 4].copy_from_slice(&entry);
+        }
+    }
+
+    /// Takes a free sector on `track`, `interleave` sectors after `previous` if possible.
+    fn allocate_on(&mut self, track: u8, previous: Option<u8>, interleave: u8) -> Option<u8> {
+        let count = sectors_in_track(track);
+        let start = previous.map_or(0, |p| (p + interleave) % count);
+        let free = &mut self.free[track as usize - 1];
+        let sector = (0..count)
+            .map(|i| (start + i) % count)
+            .find(|s| free[*s as usize])?;
+        free[sector as usize] = false;
+        Some(sector)
+    }
+
+    fn allocate(&mut self, previous: Option<(u8, u8)>) -> Result<(u8, u8), Error> {
+        let outward = (1..DIRECTORY_TRACK).flat_map(|d| {
+            let below = DIRECTORY_TRACK.checked_sub(d).filter(|t| *t >= 1);
+            let above = Some(DIRECTORY_TRACK + d).filter(|t| *t <= TRACKS);
+            below.into_iter().chain(above)
+        });
+        let tracks: Vec<u8> = previous
+            .map(|(t, _)| t)
+            .into_iter()
+            .chain(outward)
+            .collect();
+        for track in tracks {
+            let after = previous.filter(|(t, _)| *t == track).map(|(_, s)| s);
+            if let Some(sector) = self.allocate_on(track, after, FILE_INTERLEAVE) {
+                return Ok((track, sector));
+            }
+        }
+        Err(Error::DiskFull)
+    }
+
+    /// Finds an unused directory entry, adding a directory sector when all are taken.
+    fn free_entry(&mut self) -> Result<(u8, u8, usize), Error> {
+        for &(track, sector) in &self.directory {
+            let offset = Self::offset(track, sector);
+            for entry in 0..ENTRIES_PER_SECTOR {
+                if self.bytes[offset + entry * 32 + 2] == 0 {
+                    return Ok((track, sector, entry));
+                }
+            }
+        }
+        let &(last_track, last_sector) = self.directory.last().expect("No directory sector");
+        let sector = self
+            .allocate_on(DIRECTORY_TRACK, Some(last_sector), DIRECTORY_INTERLEAVE)
+            .ok_or(Error::DirectoryFull)?;
+        let previous = self.sector_mut(last_track, last_sector);
+        previous[0] = DIRECTORY_TRACK;
+        previous[1] = sector;
+        self.sector_mut(DIRECTORY_TRACK, sector)[1] = 0xFF;
+        self.directory.push((DIRECTORY_TRACK, sector));
+        Ok((DIRECTORY_TRACK, sector, 0))
+    }
+
+    /// Adds a PRG file. `bytes` start with the load address, as written by
+    /// [`prg::write`](super::prg::write).
+    pub fn add_file(&mut self, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        let name = padded_name(name)?;
+        let before = self.clone();
+        let result = self.store(&name, bytes);
+        if result.is_err() {
+            *self = before;
+        }
+        result
+    }
+
+    fn store(&mut self, name: &[u8; NAME_LENGTH], bytes: &[u8]) -> Result<(), Error> {
+        let (dir_track, dir_sector, entry) = self.free_entry()?;
+        let chunks: Vec<&[u8]> = match bytes.len() {
+            0 => vec![&[]],
+            _ => bytes.chunks(SECTOR_SIZE - 2).collect(),
+        };
+        let mut sectors = vec![];
+        for _ in &chunks {
+            let sector = self.allocate(sectors.last().copied())?;
+            sectors.push(sector);
+        }
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let (track, sector) = sectors[idx];
+            let link = match sectors.get(idx + 1) {
+                Some(&(next_track, next_sector)) => [next_track, next_sector],
+                None => [0, chunk.len() as u8 + 1],
+            };
+            let data = self.sector_mut(track, sector);
+            data[..2].copy_from_slice(&link);
+            data[2..2 + chunk.len()].copy_from_slice(chunk);
+        }
+
+        let directory = self.sector_mut(dir_track, dir_sector);
+        let at = entry * 32;
+        directory[at + 2] = FILE_TYPE_PRG;
+        directory[at + 3] = sectors[0].0;
+        directory[at + 4] = sectors[0].1;
+        directory[at + 5..at + 5 + NAME_LENGTH].copy_from_slice(name);
+        directory[at + 0x1E..at + 0x20].copy_from_slice(&(sectors.len() as u16).to_le_bytes());
+        self.write_bam();
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sector(image: &[u8], track: u8, sector: u8) -> &[u8] {
+        let offset = D64::offset(track, sector);
+        &image[offset..offset + SECTOR_SIZE]
+    }
+
+    /// Lists every file by following the directory chain and reading each file's sectors.
+    fn files(image: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut files = vec![];
+        let (mut track, mut sector_number) = (DIRECTORY_TRACK, 1);
+        while track != 0 {
+            let directory = sector(image, track, sector_number);
+            for entry in directory.chunks(32).filter(|e| e[2] != 0) {
+                let name = entry[5..21].iter().take_while(|b| **b != PADDING);
+                let name = String::from_utf8(name.copied().collect()).unwrap();
+                let mut bytes = vec![];
+                let (mut t, mut s) = (entry[3], entry[4]);
+                loop {
+                    let data = sector(image, t, s);
+                    if data[0] == 0 {
+                        bytes.extend(&data[2..data[1] as usize + 1]);
+                        break;
+                    }
+                    bytes.extend(&data[2..]);
+                    t = data[0];
+                    s = data[1];
+                }
+                files.push((name, bytes));
+            }
+            track = directory[0];
+            sector_number = directory[1];
+        }
+        files
+    }
+
+    fn free_blocks(image: &[u8]) -> u32 {
+        let bam = sector(image, DIRECTORY_TRACK, 0);
+        (1..=TRACKS)
+            .filter(|t| *t != DIRECTORY_TRACK)
+            .map(|t| bam[4 * t as usize] as u32)
+            .sum()
+    }
+
+    #[test]
+    fn new_disk() {
+        let image = D64::new("TEST DISK", "01").unwrap().to_bytes();
+        assert_eq!(174_848, image.len());
+        assert_eq!(664, free_blocks(&image));
+        let bam = sector(&image, DIRECTORY_TRACK, 0);
+        assert_eq!(b"TEST DISK\xA0", &bam[0x90..0x9A]);
+        assert_eq!(b"01\xA02A", &bam[0xA2..0xA7]);
+        assert!(files(&image).is_empty())
+    }
+
+    #[test]
+    fn add_files() {
+        let mut disk = D64::new("TEST", "01").unwrap();
+        let big: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        disk.add_file("BIG", &big).unwrap();
+        disk.add_file("SMALL", &[0x01, 0x08, 0x60]).unwrap();
+        let image = disk.to_bytes();
+        assert_eq!(
+            vec![
+                ("BIG".to_owned(), big),
+                ("SMALL".to_owned(), vec![0x01, 0x08, 0x60])
+            ],
+            files(&image)
+        );
+        assert_eq!(660, free_blocks(&image));
+        let directory = sector(&image, DIRECTORY_TRACK, 1);
+        assert_eq!(&[17, 0], &directory[3..5]);
+        assert_eq!(&[3, 0], &directory[0x1E..0x20])
+    }
+
+    #[test]
+    fn many_files() {
+        let mut disk = D64::new("TEST", "01").unwrap();
+        for idx in 0..20 {
+            disk.add_file(&format!("FILE{}", idx), &[idx]).unwrap();
+        }
+        let files = files(&disk.to_bytes());
+        assert_eq!(20, files.len());
+        assert_eq!(("FILE19".to_owned(), vec![19]), files[19])
+    }
+
+    #[test]
+    fn disk_full() {
+        let mut disk = D64::new("TEST", "01").unwrap();
+        let result = disk.add_file("HUGE", &vec![0; 700 * 254]);
+        assert_eq!(Err(Error::DiskFull), result);
+        assert_eq!(664, free_blocks(&disk.to_bytes()))
+    }
+
+    #[test]
+    fn invalid_name() {
+        let mut disk = D64::new("TEST", "01").unwrap();
+        assert_eq!(
+            Err(Error::InvalidName("A VERY LONG FILENAME".to_owned())),
+            disk.add_file("A VERY LONG FILENAME", &[])
+        )
+    }
+}
@@ -0,0 +1,299 @@
+//! Apple II DOS 3.3 disk images (`.dsk`, in DOS sector order).
+//!
+//! The image is a data disk: tracks 0 to 2 are marked as used for DOS, but hold no DOS image.
+//! Track 17 holds the VTOC and the catalog. DOS 3.3 has no disk name, so the volume number
+//! identifies the disk.
+
+const TRACKS: u8 = 35;
+const SECTORS: u8 = 16;
+const SECTOR_SIZE: usize = 256;
+const DOS_TRACKS: u8 = 3;
+const CATALOG_TRACK: u8 = 17;
+const ENTRIES_PER_SECTOR: usize = 7;
+const ENTRY_SIZE: usize = 35;
+const FIRST_ENTRY: usize = 0x0B;
+const NAME_LENGTH: usize = 30;
+const PAIRS_PER_LIST: usize = 122;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("name {0:?} is empty, longer than 30 characters or not ASCII")]
+    InvalidName(String),
+    #[error("disk is full")]
+    DiskFull,
+    #[error("catalog is full")]
+    CatalogFull,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FileType {
+    Text,
+    Integer,
+    Applesoft,
+    /// A `B` file, as written by [`apple::dos33_binary`](super::apple::dos33_binary).
+    Binary,
+}
+
+impl FileType {
+    fn code(self) -> u8 {
+        match self {
+            FileType::Text => 0x00,
+            FileType::Integer => 0x01,
+            FileType::Applesoft => 0x02,
+            FileType::Binary => 0x04,
+        }
+    }
+}
+
+/// A disk image that files can be added to one by one.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Dos33Disk {
+    bytes: Vec<u8>,
+}
+
+fn offset(track: u8, sector: u8) -> usize {
+    (track as usize * SECTORS as usize + sector as usize) * SECTOR_SIZE
+}
+
+impl Dos33Disk {
+    /// A formatted, empty disk.
+    pub fn new(volume: u8) -> Self {
+        let mut disk = Dos33Disk {
+            bytes: vec![0; TRACKS as usize * SECTORS as usize * SECTOR_SIZE],
+        };
+        let vtoc = disk.sector_mut(CATALOG_TRACK, 0);
+        vtoc[0x01] = CATALOG_TRACK;
+        vtoc[0x02] = SECTORS - 1;
+        vtoc[0x03] = 3;
+        vtoc[0x06] = volume;
+        vtoc[0x27] = PAIRS_PER_LIST as u8;
+        vtoc[0x30] = CATALOG_TRACK;
+        vtoc[0x31] = 1;
+        vtoc[0x34] = TRACKS;
+        vtoc[0x35] = SECTORS;
+        vtoc[0x36..0x38].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        for track in (DOS_TRACKS..TRACKS).filter(|t| *t != CATALOG_TRACK) {
+            let at = 0x38 + 4 * track as usize;
+            vtoc[at..at + 2].copy_from_slice(&[0xFF, 0xFF]);
+        }
+        for sector in (2..SECTORS).rev() {
+            let catalog = disk.sector_mut(CATALOG_TRACK, sector);
+            catalog[0x01] = CATALOG_TRACK;
+            catalog[0x02] = sector - 1;
+        }
+        disk
+    }
+
+    fn sector_mut(&mut self, track: u8, sector: u8) -> &mut [u8] {
+        let offset = offset(track, sector);
+        &mut self.bytes[offset..offset + SECTOR_SIZE]
+    }
+
+    /// Takes a free sector, searching outward from the catalog track and from the highest
+    /// sector down, as DOS does.
+    fn allocate(&mut self) -> Result<(u8, u8), Error> {
+        let tracks = (CATALOG_TRACK + 1..TRACKS).chain((0..CATALOG_TRACK).rev());
+        for track in tracks {
+            let at = offset(CATALOG_TRACK, 0) + 0x38 + 4 * track as usize;
+            let bitmap = u16::from_be_bytes([self.bytes[at], self.bytes[at + 1]]);
+            if let Some(sector) = (0..SECTORS).rev().find(|s| bitmap & (1 << s) != 0) {
+                let bitmap = bitmap & !(1 << sector);
+                self.bytes[at..at + 2].copy_from_slice(&bitmap.to_be_bytes());
+                return Ok((track, sector));
+            }
+        }
+        Err(Error::DiskFull)
+    }
+
+    fn free_entry(&self) -> Result<usize, Error> {
+        let (mut track, mut sector) = (CATALOG_TRACK, SECTORS - 1);
+        while track != 0 {
+            let catalog = offset(track, sector);
+            for entry in 0..ENTRIES_PER_SECTOR {
+                let at = catalog + FIRST_ENTRY + entry * ENTRY_SIZE;
+                if self.bytes[at] == 0 {
+                    return Ok(at);
+                }
+            }
+            track = self.bytes[catalog + 1];
+            sector = self.bytes[catalog + 2];
+        }
+        Err(Error::CatalogFull)
+    }
+
+    pub fn add_file(&mut self, name: &str, file_type: FileType, bytes: &[u8]) -> Result<(), Error> {
+        if name.is_empty() || name.len() > NAME_LENGTH || !name.is_ascii() {
+            return Err(Error::InvalidName(name.to_owned()));
+        }
+        let before = self.clone();
+        let result = self.store(name, file_type, bytes);
+        if result.is_err() {
+            *self = before;
+        }
+        result
+    }
+
+    fn store(&mut self, name: &str, file_type: FileType, bytes: &[u8]) -> Result<(), Error> {
+        let entry = self.free_entry()?;
+        let chunks: Vec<&[u8]> = bytes.chunks(SECTOR_SIZE).collect();
+        let lists = std::cmp::max(1, chunks.len().div_ceil(PAIRS_PER_LIST));
+        let mut list_sectors = vec![];
+        for _ in 0..lists {
+            list_sectors.push(self.allocate()?);
+        }
+        let mut data_sectors = vec![];
+        for chunk in &chunks {
+            let (track, sector) = self.allocate()?;
+            self.sector_mut(track, sector)[..chunk.len()].copy_from_slice(chunk);
+            data_sectors.push((track, sector));
+        }
+        for (idx, &(track, sector)) in list_sectors.iter().enumerate() {
+            let next = list_sectors.get(idx + 1).copied().unwrap_or((0, 0));
+            let pairs = data_sectors.iter().skip(idx * PAIRS_PER_LIST);
+            let list = self.sector_mut(track, sector);
+            list[0x01] = next.0;
+            list[0x02] = next.1;
+            list[0x05..0x07].copy_from_slice(&((idx * PAIRS_PER_LIST) as u16).to_le_bytes());
+            for (pair, &(t, s)) in pairs.take(PAIRS_PER_LIST).enumerate() {
+                list[0x0C + 2 * pair] = t;
+                list[0x0D + 2 * pair] = s;
+            }
+        }
+
+        let entry = &mut self.bytes[entry..entry + ENTRY_SIZE];
+        entry[0x00] = list_sectors[0].0;
+        entry[0x01] = list_sectors[0].1;
+        entry[0x02] = file_type.code();
+        for (idx, byte) in entry[0x03..0x21].iter_mut().enumerate() {
+            *byte = name.as_bytes().get(idx).copied().unwrap_or(b' ') | 0x80;
+        }
+        let length = (list_sectors.len() + data_sectors.len()) as u16;
+        entry[0x21..0x23].copy_from_slice(&length.to_le_bytes());
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::LinkerConfig;
+    use crate::output::apple;
+
+    fn sector(image: &[u8], track: u8, sector: u8) -> &[u8] {
+        &image[offset(track, sector)..offset(track, sector) + SECTOR_SIZE]
+    }
+
+    /// Lists every file by following the catalog chain and each file's track/sector lists.
+    fn files(image: &[u8]) -> Vec<(String, u8, Vec<u8>)> {
+        let mut files = vec![];
+        let (mut track, mut sector_number) = (CATALOG_TRACK, SECTORS - 1);
+        while track != 0 {
+            let catalog = sector(image, track, sector_number);
+            for entry in catalog[FIRST_ENTRY..]
+                .chunks(ENTRY_SIZE)
+                .take(ENTRIES_PER_SECTOR)
+            {
+                if entry[0] == 0 {
+                    continue;
+                }
+                let name: String = entry[0x03..0x21]
+                    .iter()
+                    .map(|b| (b & 0x7F) as char)
+                    .collect();
+                let mut bytes = vec![];
+                let (mut t, mut s) = (entry[0], entry[1]);
+                while t != 0 {
+                    let list = sector(image, t, s);
+                    for pair in list[0x0C..].chunks(2).take_while(|p| p[0] != 0) {
+                        bytes.extend(sector(image, pair[0], pair[1]));
+                    }
+                    t = list[0x01];
+                    s = list[0x02];
+                }
+                files.push((name.trim_end().to_owned(), entry[0x02], bytes));
+            }
+            track = catalog[0x01];
+            sector_number = catalog[0x02];
+        }
+        files
+    }
+
+    fn free_sectors(image: &[u8]) -> u32 {
+        let vtoc = sector(image, CATALOG_TRACK, 0);
+        vtoc[0x38..0x38 + 4 * TRACKS as usize]
+            .iter()
+            .map(|b| b.count_ones())
+            .sum()
+    }
+
+    #[test]
+    fn new_disk() {
+        let image = Dos33Disk::new(254).to_bytes();
+        assert_eq!(143_360, image.len());
+        assert_eq!(496, free_sectors(&image));
+        let vtoc = sector(&image, CATALOG_TRACK, 0);
+        assert_eq!(&[0x00, 17, 15, 3], &vtoc[..4]);
+        assert_eq!(254, vtoc[0x06]);
+        assert_eq!(&[17, 1], &sector(&image, CATALOG_TRACK, 2)[1..3]);
+        assert!(files(&image).is_empty())
+    }
+
+    #[test]
+    fn add_binary() {
+        let config: LinkerConfig = "CODE: start = $0800, size = $1000;".parse().unwrap();
+        let layout = assemble_layout("  .org $0300\n  RTS\n", &config).unwrap();
+        let binary = apple::dos33_binary(&layout).unwrap();
+        let mut disk = Dos33Disk::new(254);
+        disk.add_file("HELLO", FileType::Binary, &binary).unwrap();
+        let image = disk.to_bytes();
+
+        let files = files(&image);
+        assert_eq!(1, files.len());
+        let (name, file_type, bytes) = &files[0];
+        assert_eq!("HELLO", name);
+        assert_eq!(0x04, *file_type);
+        assert_eq!(&binary[..], &bytes[..binary.len()]);
+        assert_eq!(494, free_sectors(&image));
+        let entry = &sector(&image, CATALOG_TRACK, 15)[FIRST_ENTRY..FIRST_ENTRY + ENTRY_SIZE];
+        assert_eq!(&[18, 15, 0x04, b'H' | 0x80], &entry[..4]);
+        assert_eq!(&[2, 0], &entry[0x21..0x23])
+    }
+
+    #[test]
+    fn many_files() {
+        let mut disk = Dos33Disk::new(1);
+        let big: Vec<u8> = (0..300 * 256).map(|i| (i / 256) as u8).collect();
+        disk.add_file("BIG", FileType::Binary, &big).unwrap();
+        for idx in 0..10 {
+            let name = format!("FILE{}", idx);
+            disk.add_file(&name, FileType::Text, &[idx]).unwrap();
+        }
+        let files = files(&disk.to_bytes());
+        assert_eq!(11, files.len());
+        assert_eq!(big, files[0].2);
+        assert_eq!("FILE9", files[10].0);
+        assert_eq!(9, files[10].2[0])
+    }
+
+    #[test]
+    fn disk_full() {
+        let mut disk = Dos33Disk::new(254);
+        let result = disk.add_file("HUGE", FileType::Binary, &vec![0; 500 * 256]);
+        assert_eq!(Err(Error::DiskFull), result);
+        assert_eq!(496, free_sectors(&disk.to_bytes()))
+    }
+
+    #[test]
+    fn invalid_name() {
+        let mut disk = Dos33Disk::new(254);
+        assert_eq!(
+            Err(Error::InvalidName(String::new())),
+            disk.add_file("", FileType::Binary, &[])
+        )
+    }
+}
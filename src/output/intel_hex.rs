@@ -0,0 +1,289 @@
+//! Intel HEX, as accepted by most EPROM and EEPROM programmers.
+//!
+//! Every non-empty segment is written at its own address, so gaps between segments and `.org`
+//! fragments are left out instead of being padded. Extended linear address records are written
+//! whenever a record lies above the first 64K, which only happens with a base address.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use crate::linker::Layout;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("record length must be at least 1")]
+    RecordLength,
+    #[error("segment {0} does not fit below 4G at the base address")]
+    TooLarge(String),
+    #[error("line {0} is not a valid record")]
+    Syntax(usize),
+    #[error("line {0} has a bad checksum")]
+    Checksum(usize),
+    #[error("line {line} has unsupported record type {kind:#04X}")]
+    UnsupportedRecord { line: usize, kind: u8 },
+    #[error("missing end of file record")]
+    MissingEnd,
+    #[error("line {0} has data past 4G")]
+    AddressOverflow(usize),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    /// Data bytes per record.
+    pub record_length: u8,
+    /// Added to every address, for images that go somewhere other than address 0 of the device.
+    pub base_address: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            record_length: 16,
+            base_address: 0,
+        }
+    }
+}
+
+/// Consecutive bytes starting at `address`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Region {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+pub fn write(layout: &Layout, options: &Options) -> Result<String, Error> {
+    if options.record_length == 0 {
+        return Err(Error::RecordLength);
+    }
+    let mut segments: Vec<_> = layout
+        .segments
+        .iter()
+        .filter(|s| !s.bytes.is_empty())
+        .collect();
+    segments.sort_by_key(|s| s.start);
+
+    let mut out = String::new();
+    let mut upper = 0;
+    for segment in segments {
+        let too_large = || Error::TooLarge(segment.name.clone());
+        let start = options
+            .base_address
+            .checked_add(segment.start as u32)
+            .ok_or_else(too_large)?;
+        u32::try_from(start as u64 + segment.bytes.len() as u64 - 1).map_err(|_| too_large())?;
+
+        let mut rest = &segment.bytes[..];
+        let mut address = start;
+        while !rest.is_empty() {
+            if address >> 16 != upper {
+                upper = address >> 16;
+                record(
+                    &mut out,
+                    EXTENDED_LINEAR_ADDRESS,
+                    0,
+                    &(upper as u16).to_be_bytes(),
+                );
+            }
+            let to_boundary = 0x10000 - (address & 0xFFFF) as usize;
+            let len = rest
+                .len()
+                .min(options.record_length as usize)
+                .min(to_boundary);
+            let (data, remaining) = rest.split_at(len);
+            record(&mut out, DATA, address as u16, data);
+            rest = remaining;
+            address = address.wrapping_add(len as u32);
+        }
+    }
+    record(&mut out, END_OF_FILE, 0, &[]);
+    Ok(out)
+}
+
+fn record(out: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let [high, low] = address.to_be_bytes();
+    let mut bytes = vec![data.len() as u8, high, low, kind];
+    bytes.extend(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    out.push(':');
+    for byte in bytes.iter().chain(&[checksum]) {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+/// Reads the data records of a file, merging records that continue where the previous one
+/// ended.
+pub fn read(s: &str) -> Result<Vec<Region>, Error> {
+    let mut regions: Vec<Region> = vec![];
+    let mut base = 0u32;
+    for (idx, text) in s.lines().enumerate() {
+        let line = idx + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let bytes = parse_record(text).ok_or(Error::Syntax(line))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(Error::Syntax(line));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(Error::Checksum(line));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            DATA => {
+                let address = base.wrapping_add(address);
+                // Every region ends below 4G, so comparing against their ends can't overflow.
+                address
+                    .checked_add(data.len() as u32)
+                    .ok_or(Error::AddressOverflow(line))?;
+                match regions.last_mut() {
+                    Some(last) if last.address + last.bytes.len() as u32 == address => {
+                        last.bytes.extend(data)
+                    }
+                    _ => regions.push(Region {
+                        address,
+                        bytes: data.to_vec(),
+                    }),
+                }
+            }
+            END_OF_FILE => return Ok(regions),
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                let value = u16::from_be_bytes([data[0], data[1]]) as u32;
+                base = match bytes[3] {
+                    EXTENDED_SEGMENT_ADDRESS => value << 4,
+                    _ => value << 16,
+                };
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            kind => return Err(Error::UnsupportedRecord { line, kind }),
+        }
+    }
+    Err(Error::MissingEnd)
+}
+
+fn parse_record(text: &str) -> Option<Vec<u8>> {
+    let hex = text.strip_prefix(':')?;
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::{LinkerConfig, Segment};
+
+    fn layout(segments: Vec<(u16, Vec<u8>)>) -> Layout {
+        Layout {
+            segments: segments
+                .into_iter()
+                .map(|(start, bytes)| Segment {
+                    name: format!("S{:04X}", start),
+                    start,
+                    bytes,
+                })
+                .collect(),
+            symbols: vec![],
+            relocations: vec![],
+            unresolved: vec![],
+            lines: vec![],
+            entry: None,
+            inits: vec![],
+            orgs: vec![],
+            failed_checks: vec![],
+        }
+    }
+
+    #[test]
+    fn write_success() {
+        let layout = layout(vec![(0x0100, vec![0x60, 0x4C, 0x00, 0x01, 0xEA])]);
+        let options = Options {
+            record_length: 4,
+            ..Options::default()
+        };
+        assert_eq!(
+            Ok(":04010000604C00014E\n:01010400EA10\n:00000001FF\n".to_owned()),
+            write(&layout, &options)
+        )
+    }
+
+    #[test]
+    fn org_gaps_round_trip() {
+        let config: LinkerConfig = "CODE: start = $8000, size = $0100;".parse().unwrap();
+        let layout =
+            assemble_layout("start:\n  JMP start\n  .org $FFFC\n  JMP start\n", &config).unwrap();
+        let hex = write(&layout, &Options::default()).unwrap();
+        assert_eq!(
+            Ok(vec![
+                Region {
+                    address: 0x8000,
+                    bytes: vec![0x4C, 0x00, 0x80]
+                },
+                Region {
+                    address: 0xFFFC,
+                    bytes: vec![0x4C, 0x00, 0x80]
+                },
+            ]),
+            read(&hex)
+        )
+    }
+
+    #[test]
+    fn extended_linear_address() {
+        let layout = layout(vec![(0xFFFE, vec![1, 2, 3, 4])]);
+        let options = Options {
+            base_address: 0x1_0000,
+            ..Options::default()
+        };
+        let hex = write(&layout, &options).unwrap();
+        assert_eq!(
+            ":020000040001F9\n:02FFFE000102FE\n:020000040002F8\n:020000000304F7\n:00000001FF\n",
+            hex
+        );
+        assert_eq!(
+            Ok(vec![Region {
+                address: 0x1_FFFE,
+                bytes: vec![1, 2, 3, 4]
+            }]),
+            read(&hex)
+        )
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert_eq!(
+            Err(Error::Checksum(1)),
+            read(":04010000604C0001FF\n:00000001FF\n")
+        )
+    }
+
+    #[test]
+    fn address_overflow() {
+        assert_eq!(
+            Err(Error::AddressOverflow(2)),
+            read(":02000004FFFFFC\n:02FFFF00AABB9B\n:00000001FF\n")
+        )
+    }
+
+    #[test]
+    fn missing_end() {
+        assert_eq!(Err(Error::MissingEnd), read(":01010400EA10\n"))
+    }
+}
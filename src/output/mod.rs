@@ -0,0 +1,16 @@
+//! Writers for the file formats the assembled program can be delivered in.
+
+pub mod apple;
+pub mod d64;
+pub mod dsk;
+pub mod intel_hex;
+pub mod monitor;
+pub mod nes;
+pub mod o65;
+pub mod patch;
+pub mod prg;
+pub mod rom;
+pub mod srec;
+pub mod tap;
+pub mod wav;
+pub mod xex;
@@ -0,0 +1,192 @@
+//! Text for pasting into a ROM monitor over a serial line: Wozmon deposit commands and KIM-1
+//! paper tape.
+//!
+//! Lines end in CR LF. Wozmon acts on the CR and skips the LF.
+
+use std::convert::TryFrom;
+
+use crate::linker::{Layout, Segment};
+
+/// Wozmon reads at most 127 characters per line.
+const WOZMON_MAX_BYTES: u8 = 40;
+const PAPER_TAPE_MAX_BYTES: u8 = 24;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("line length has to be between 1 and {0} bytes")]
+    LineLength(u8),
+    #[error("{0} records do not fit in the record count of a paper tape")]
+    TooManyRecords(usize),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    pub bytes_per_line: u8,
+    /// Ends with a run command for the `.entry` label, or the first segment without one.
+    pub run: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            bytes_per_line: 8,
+            run: false,
+        }
+    }
+}
+
+fn segments(layout: &Layout) -> Vec<&Segment> {
+    let mut segments: Vec<_> = layout
+        .segments
+        .iter()
+        .filter(|s| !s.bytes.is_empty())
+        .collect();
+    segments.sort_by_key(|s| s.start);
+    segments
+}
+
+/// Each line holds `0300: A9 00 8D ...`, and a run command is written as `0300R`.
+pub fn wozmon(layout: &Layout, options: &Options) -> Result<String, Error> {
+    if options.bytes_per_line == 0 || options.bytes_per_line > WOZMON_MAX_BYTES {
+        return Err(Error::LineLength(WOZMON_MAX_BYTES));
+    }
+    let segments = segments(layout);
+    let mut out = String::new();
+    for segment in &segments {
+        let lines = segment.bytes.chunks(options.bytes_per_line as usize);
+        for (idx, line) in lines.enumerate() {
+            let address = segment.start as usize + idx * options.bytes_per_line as usize;
+            out.push_str(&format!("{:04X}:", address));
+            for byte in line {
+                out.push_str(&format!(" {:02X}", byte));
+            }
+            out.push_str("\r\n");
+        }
+    }
+    if options.run {
+        if let Some(run) = layout.entry.or_else(|| segments.first().map(|s| s.start)) {
+            out.push_str(&format!("{:04X}R\r\n", run));
+        }
+    }
+    Ok(out)
+}
+
+/// KIM-1 paper tape: `;`, the byte count, the address, the bytes and a 16-bit sum of all of
+/// them. The last record has no bytes and holds the number of records instead of an address,
+/// so there can be at most $FFFF records before it.
+pub fn paper_tape(layout: &Layout, bytes_per_record: u8) -> Result<String, Error> {
+    if bytes_per_record == 0 || bytes_per_record > PAPER_TAPE_MAX_BYTES {
+        return Err(Error::LineLength(PAPER_TAPE_MAX_BYTES));
+    }
+    let mut out = String::new();
+    let mut records = 0;
+    for segment in segments(layout) {
+        for (idx, data) in segment.bytes.chunks(bytes_per_record as usize).enumerate() {
+            let address = segment
+                .start
+                .wrapping_add((idx * bytes_per_record as usize) as u16);
+            let mut record = vec![data.len() as u8];
+            record.extend(&address.to_be_bytes());
+            record.extend(data);
+            out.push_str(&record_line(&record));
+            records += 1;
+        }
+    }
+    let records = u16::try_from(records).map_err(|_| Error::TooManyRecords(records))?;
+    let mut last = vec![0];
+    last.extend(&records.to_be_bytes());
+    out.push_str(&record_line(&last));
+    Ok(out)
+}
+
+fn record_line(record: &[u8]) -> String {
+    let checksum = record
+        .iter()
+        .fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+    let mut line = String::from(";");
+    for byte in record {
+        line.push_str(&format!("{:02X}", byte));
+    }
+    line.push_str(&format!("{:04X}\r\n", checksum));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::LinkerConfig;
+
+    fn layout(source: &str) -> Layout {
+        let config: LinkerConfig = "CODE: start = $0300, size = $1000;".parse().unwrap();
+        assemble_layout(source, &config).unwrap()
+    }
+
+    fn layout_with(segments: Vec<Segment>) -> Layout {
+        Layout {
+            segments,
+            symbols: vec![],
+            relocations: vec![],
+            unresolved: vec![],
+            lines: vec![],
+            entry: None,
+            inits: vec![],
+            orgs: vec![],
+            failed_checks: vec![],
+        }
+    }
+
+    #[test]
+    fn wozmon_success() {
+        let layout =
+            layout("  .entry start\n  RTS\nstart:\n  LDA #$00\n  STZ $0200\n  JMP start\n");
+        let options = Options {
+            bytes_per_line: 4,
+            run: true,
+        };
+        assert_eq!(
+            Ok("0300: 60 A9 00 9C\r\n0304: 00 02 4C 01\r\n0308: 03\r\n0301R\r\n".to_owned()),
+            wozmon(&layout, &options)
+        )
+    }
+
+    #[test]
+    fn wozmon_without_run() {
+        let layout = layout("  RTS\n  .org $0280\n  RTS\n");
+        assert_eq!(
+            Ok("0280: 60\r\n0300: 60\r\n".to_owned()),
+            wozmon(&layout, &Options::default())
+        )
+    }
+
+    #[test]
+    fn wozmon_line_length() {
+        let options = Options {
+            bytes_per_line: 41,
+            run: false,
+        };
+        assert_eq!(
+            Err(Error::LineLength(40)),
+            wozmon(&layout("  RTS\n"), &options)
+        )
+    }
+
+    #[test]
+    fn paper_tape_success() {
+        let layout = layout("  LDA #$00\n  STZ $0200\n  RTS\n");
+        assert_eq!(
+            Ok(";040300A9009C00014C\r\n;0203040260006B\r\n;0000020002\r\n".to_owned()),
+            paper_tape(&layout, 4)
+        )
+    }
+
+    #[test]
+    fn paper_tape_too_many_records() {
+        let layout = layout_with(vec![Segment {
+            name: "CODE".to_owned(),
+            start: 0x0000,
+            bytes: vec![0xEA; 0x10000],
+        }]);
+        assert_eq!(Err(Error::TooManyRecords(0x10000)), paper_tape(&layout, 1))
+    }
+}
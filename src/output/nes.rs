@@ -0,0 +1,284 @@
+//! iNES and NES 2.0 ROM images.
+//!
+//! PRG banks are 16K and CHR banks 8K. Each bank is mapped at a CPU (or PPU) address and
+//! holds the listed segments, which have to lie in its window. Unused bytes are set to `$FF`.
+//! The last PRG bank is the fixed bank and has to hold the NMI, RESET and IRQ vectors at
+//! `$FFFA`.
+
+use crate::linker::{Layout, Segment};
+
+const MAGIC: [u8; 4] = *b"NES\x1A";
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const VECTORS: u16 = 0xFFFA;
+const FILL: u8 = 0xFF;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("segment {0} does not exist")]
+    UnknownSegment(String),
+    #[error("segment {0} is not assigned to a bank")]
+    UnassignedSegment(String),
+    #[error("segment {segment} lies outside its bank at {address:#06X}")]
+    OutsideBank { segment: String, address: u16 },
+    #[error("there are no PRG banks")]
+    NoPrgBanks,
+    #[error("the fixed bank does not hold the NMI, RESET and IRQ vectors at $FFFA")]
+    MissingVectors,
+    #[error("mapper {0} needs a NES 2.0 header")]
+    MapperOutOfRange(u16),
+    #[error("too many {0} banks")]
+    TooManyBanks(&'static str),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+/// The segments that make up one bank, and the address the bank is mapped at.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Bank {
+    pub address: u16,
+    pub segments: Vec<String>,
+}
+
+impl Bank {
+    pub fn new(address: u16, segments: &[&str]) -> Self {
+        Bank {
+            address,
+            segments: segments.iter().map(|&s| s.to_owned()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    pub mapper: u16,
+    /// Only written in NES 2.0 headers.
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub nes2: bool,
+    pub prg: Vec<Bank>,
+    pub chr: Vec<Bank>,
+}
+
+impl Default for Options {
+    /// NROM-128: one PRG bank at `$C000` with the `CODE` segment.
+    fn default() -> Self {
+        Options {
+            mapper: 0,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            nes2: false,
+            prg: vec![Bank::new(0xC000, &["CODE"])],
+            chr: vec![],
+        }
+    }
+}
+
+pub fn write(layout: &Layout, options: &Options) -> Result<Vec<u8>, Error> {
+    for segment in layout.segments.iter().filter(|s| !s.bytes.is_empty()) {
+        let assigned = options
+            .prg
+            .iter()
+            .chain(&options.chr)
+            .any(|b| b.segments.contains(&segment.name));
+        if !assigned {
+            return Err(Error::UnassignedSegment(segment.name.clone()));
+        }
+    }
+    let fixed = options.prg.last().ok_or(Error::NoPrgBanks)?;
+    let holds_vectors = fixed.address as usize + PRG_BANK_SIZE == 0x10000
+        && (VECTORS..=0xFFFF).all(|address| {
+            fixed
+                .segments
+                .iter()
+                .filter_map(|name| layout.segments.iter().find(|s| &s.name == name))
+                .any(|s| address >= s.start && ((address - s.start) as usize) < s.bytes.len())
+        });
+    if !holds_vectors {
+        return Err(Error::MissingVectors);
+    }
+    if !options.nes2 && options.mapper > 0xFF {
+        return Err(Error::MapperOutOfRange(options.mapper));
+    }
+    let max_banks = if options.nes2 { 0xEFF } else { 0xFF };
+    if options.prg.len() > max_banks {
+        return Err(Error::TooManyBanks("PRG"));
+    }
+    if options.chr.len() > max_banks {
+        return Err(Error::TooManyBanks("CHR"));
+    }
+
+    let mut out = MAGIC.to_vec();
+    out.push(options.prg.len() as u8);
+    out.push(options.chr.len() as u8);
+    let mirroring = match options.mirroring {
+        Mirroring::Horizontal => 0x00,
+        Mirroring::Vertical => 0x01,
+        Mirroring::FourScreen => 0x08,
+    };
+    let [mapper_low, mapper_high] = options.mapper.to_le_bytes();
+    out.push((mapper_low << 4) | ((options.battery as u8) << 1) | mirroring);
+    let nes2 = if options.nes2 { 0x08 } else { 0x00 };
+    out.push((mapper_low & 0xF0) | nes2);
+    if options.nes2 {
+        out.push((options.submapper << 4) | (mapper_high & 0x0F));
+        let [_, prg_high] = (options.prg.len() as u16).to_le_bytes();
+        let [_, chr_high] = (options.chr.len() as u16).to_le_bytes();
+        out.push((chr_high << 4) | prg_high);
+        out.extend(&[0; 6]);
+    } else {
+        out.extend(&[0; 8]);
+    }
+
+    for bank in &options.prg {
+        out.extend(bank_bytes(layout, bank, PRG_BANK_SIZE)?);
+    }
+    for bank in &options.chr {
+        out.extend(bank_bytes(layout, bank, CHR_BANK_SIZE)?);
+    }
+    Ok(out)
+}
+
+fn bank_bytes(layout: &Layout, bank: &Bank, size: usize) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![FILL; size];
+    let start = bank.address as usize;
+    for name in &bank.segments {
+        let segment: &Segment = layout
+            .segments
+            .iter()
+            .find(|s| &s.name == name)
+            .ok_or_else(|| Error::UnknownSegment(name.clone()))?;
+        for (idx, byte) in segment.bytes.iter().enumerate() {
+            let address = segment.start as usize + idx;
+            if address < start || address >= start + size {
+                return Err(Error::OutsideBank {
+                    segment: name.clone(),
+                    address: address as u16,
+                });
+            }
+            bytes[address - start] = *byte;
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(segments: Vec<(&str, u16, Vec<u8>)>) -> Layout {
+        Layout {
+            segments: segments
+                .into_iter()
+                .map(|(name, start, bytes)| Segment {
+                    name: name.to_owned(),
+                    start,
+                    bytes,
+                })
+                .collect(),
+            symbols: vec![],
+            relocations: vec![],
+            unresolved: vec![],
+            lines: vec![],
+            entry: None,
+            inits: vec![],
+            orgs: vec![],
+            failed_checks: vec![],
+        }
+    }
+
+    fn vectors() -> (&'static str, u16, Vec<u8>) {
+        ("VECTORS", 0xFFFA, vec![0x00, 0xC0, 0x01, 0xC0, 0x02, 0xC0])
+    }
+
+    #[test]
+    fn nrom() {
+        let layout = layout(vec![("CODE", 0xC000, vec![0x60]), vectors()]);
+        let options = Options {
+            mirroring: Mirroring::Vertical,
+            prg: vec![Bank::new(0xC000, &["CODE", "VECTORS"])],
+            ..Options::default()
+        };
+        let result = write(&layout, &options).unwrap();
+        assert_eq!(16 + 0x4000, result.len());
+        assert_eq!(
+            &[b'N', b'E', b'S', 0x1A, 1, 0, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0],
+            &result[..16]
+        );
+        assert_eq!(&[0x60, 0xFF], &result[16..18]);
+        assert_eq!(
+            &[0x00, 0xC0, 0x01, 0xC0, 0x02, 0xC0],
+            &result[16 + 0x3FFA..]
+        )
+    }
+
+    #[test]
+    fn shared_address_range() {
+        let layout = layout(vec![
+            ("BANK0", 0x8000, vec![0x00]),
+            ("BANK1", 0x8000, vec![0x01]),
+            ("CHARS", 0x0000, vec![0xAA]),
+            ("FIXED", 0xC000, vec![0x60]),
+            vectors(),
+        ]);
+        let options = Options {
+            mapper: 2,
+            prg: vec![
+                Bank::new(0x8000, &["BANK0"]),
+                Bank::new(0x8000, &["BANK1"]),
+                Bank::new(0xC000, &["FIXED", "VECTORS"]),
+            ],
+            chr: vec![Bank::new(0x0000, &["CHARS"])],
+            ..Options::default()
+        };
+        let result = write(&layout, &options).unwrap();
+        assert_eq!(16 + 3 * 0x4000 + 0x2000, result.len());
+        assert_eq!(&[3, 1, 0x20, 0x00], &result[4..8]);
+        assert_eq!(0x00, result[16]);
+        assert_eq!(0x01, result[16 + 0x4000]);
+        assert_eq!(0x60, result[16 + 0x8000]);
+        assert_eq!(0xAA, result[16 + 0xC000])
+    }
+
+    #[test]
+    fn nes2_header() {
+        let layout = layout(vec![vectors()]);
+        let options = Options {
+            mapper: 0x123,
+            submapper: 4,
+            battery: true,
+            nes2: true,
+            prg: vec![Bank::new(0xC000, &["VECTORS"])],
+            ..Options::default()
+        };
+        let result = write(&layout, &options).unwrap();
+        assert_eq!(&[1, 0, 0x32, 0x28, 0x41, 0x00], &result[4..10])
+    }
+
+    #[test]
+    fn missing_vectors() {
+        let layout = layout(vec![("CODE", 0xC000, vec![0x60])]);
+        assert_eq!(
+            Err(Error::MissingVectors),
+            write(&layout, &Options::default())
+        )
+    }
+
+    #[test]
+    fn mapper_out_of_range() {
+        let layout = layout(vec![vectors()]);
+        let options = Options {
+            mapper: 256,
+            prg: vec![Bank::new(0xC000, &["VECTORS"])],
+            ..Options::default()
+        };
+        assert_eq!(Err(Error::MapperOutOfRange(256)), write(&layout, &options))
+    }
+}
@@ -0,0 +1,342 @@
+//! The o65 relocatable binary format, as loaded by LUnix, GeckOS and cc65 modules.
+//!
+//! Segments from the layout are grouped into the four o65 segments; each group has to be
+//! placed in ascending, non-overlapping addresses, and gaps between its segments are zero
+//! filled. Every reference to a label is written to the relocation tables, so a loader can move
+//! the segments anywhere.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::linker::{Layout, PlacedTarget, Segment};
+use crate::object::RelocationKind;
+
+const MARKER: [u8; 5] = [0x01, 0x00, b'o', b'6', b'5'];
+const VERSION: u8 = 0;
+const MODE_OBJECT: u16 = 0x1000;
+
+const SEGMENT_UNDEFINED: u8 = 0;
+const SEGMENT_TEXT: u8 = 2;
+const SEGMENT_DATA: u8 = 3;
+const SEGMENT_BSS: u8 = 4;
+const SEGMENT_ZERO: u8 = 5;
+
+const RELOCATION_WORD: u8 = 0x80;
+const RELOCATION_HIGH: u8 = 0x40;
+const RELOCATION_LOW: u8 = 0x20;
+
+const OPTION_ASSEMBLER: u8 = 2;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("segment {0} is not assigned to an o65 segment")]
+    UnmappedSegment(String),
+    #[error("segment {0} is placed before the segment it follows in its o65 segment")]
+    SegmentOrder(String),
+    #[error("o65 segments {0} and {1} overlap")]
+    Overlap(&'static str, &'static str),
+    #[error("segment {0} is uninitialized in o65 but contains bytes")]
+    InitializedBss(String),
+    #[error("relocation at {0:#06X} cannot be expressed in o65")]
+    UnsupportedRelocation(u16),
+    #[error("{0} does not fit in an o65 file")]
+    TooLarge(&'static str),
+}
+
+/// Which layout segments make up each o65 segment, in address order.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    pub text: Vec<String>,
+    pub data: Vec<String>,
+    pub bss: Vec<String>,
+    pub zero: Vec<String>,
+    /// Marks the file as an object file rather than an executable.
+    pub object_file: bool,
+    pub stack_size: u16,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let names = |names: &[&str]| names.iter().map(|&n| n.to_owned()).collect();
+        Options {
+            text: names(&["CODE", "RODATA"]),
+            data: names(&["DATA"]),
+            bss: names(&["BSS"]),
+            zero: names(&["ZEROPAGE"]),
+            object_file: false,
+            stack_size: 0,
+        }
+    }
+}
+
+struct O65Segment {
+    name: &'static str,
+    base: u16,
+    bytes: Vec<u8>,
+}
+
+impl O65Segment {
+    fn collect(
+        name: &'static str,
+        names: &[String],
+        layout: &Layout,
+        initialized: bool,
+    ) -> Result<Self, Error> {
+        let segments: Vec<&Segment> = names
+            .iter()
+            .filter_map(|n| layout.segments.iter().find(|s| &s.name == n))
+            .collect();
+        let base = segments.first().map_or(0, |s| s.start);
+        let mut bytes = vec![];
+        for segment in segments {
+            if !initialized && !segment.bytes.is_empty() {
+                return Err(Error::InitializedBss(segment.name.clone()));
+            }
+            if segment.start < base || ((segment.start - base) as usize) < bytes.len() {
+                return Err(Error::SegmentOrder(segment.name.clone()));
+            }
+            let offset = (segment.start - base) as usize;
+            bytes.resize(offset, 0);
+            bytes.extend(&segment.bytes);
+        }
+        Ok(O65Segment { name, base, bytes })
+    }
+
+    fn end(&self) -> u32 {
+        self.base as u32 + self.bytes.len() as u32
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        address >= self.base && (address as u32) < self.end()
+    }
+}
+
+pub fn write(layout: &Layout, options: &Options) -> Result<Vec<u8>, Error> {
+    let segments = [
+        O65Segment::collect("text", &options.text, layout, true)?,
+        O65Segment::collect("data", &options.data, layout, true)?,
+        O65Segment::collect("bss", &options.bss, layout, false)?,
+        O65Segment::collect("zero", &options.zero, layout, false)?,
+    ];
+    for (idx, segment) in segments.iter().enumerate() {
+        for other in &segments[..idx] {
+            if !segment.bytes.is_empty()
+                && !other.bytes.is_empty()
+                && (segment.base as u32) < other.end()
+                && (other.base as u32) < segment.end()
+            {
+                return Err(Error::Overlap(other.name, segment.name));
+            }
+        }
+    }
+
+    let mut segment_ids: HashMap<&str, u8> = HashMap::new();
+    for (names, id) in [
+        (&options.text, SEGMENT_TEXT),
+        (&options.data, SEGMENT_DATA),
+        (&options.bss, SEGMENT_BSS),
+        (&options.zero, SEGMENT_ZERO),
+    ]
+    .iter()
+    {
+        for name in names.iter() {
+            segment_ids.insert(name, *id);
+        }
+    }
+    let segment_id = |name: &str| {
+        segment_ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::UnmappedSegment(name.to_owned()))
+    };
+    for segment in layout.segments.iter().filter(|s| !s.bytes.is_empty()) {
+        segment_id(&segment.name)?;
+    }
+
+    let mut out = MARKER.to_vec();
+    out.push(VERSION);
+    let mode = if options.object_file { MODE_OBJECT } else { 0 };
+    out.extend(&mode.to_le_bytes());
+    for segment in &segments {
+        let len = u16::try_from(segment.bytes.len()).map_err(|_| Error::TooLarge("segment"))?;
+        out.extend(&segment.base.to_le_bytes());
+        out.extend(&len.to_le_bytes());
+    }
+    out.extend(&options.stack_size.to_le_bytes());
+
+    let assembler = b"sfota\0";
+    out.push(assembler.len() as u8 + 2);
+    out.push(OPTION_ASSEMBLER);
+    out.extend(assembler);
+    out.push(0);
+
+    out.extend(&segments[0].bytes);
+    out.extend(&segments[1].bytes);
+
+    write_count(&mut out, layout.unresolved.len())?;
+    for name in &layout.unresolved {
+        write_name(&mut out, name);
+    }
+
+    for segment in &segments[..2] {
+        let mut relocations: Vec<_> = layout
+            .relocations
+            .iter()
+            .filter(|r| segment.contains(r.address))
+            .collect();
+        relocations.sort_by_key(|r| r.address);
+        let mut last = segment.base as i32 - 1;
+        for relocation in relocations {
+            let mut distance = relocation.address as i32 - last;
+            while distance > 254 {
+                out.push(255);
+                distance -= 254;
+            }
+            out.push(distance as u8);
+            last = relocation.address as i32;
+
+            let (id, undefined_index) = match &relocation.target {
+                PlacedTarget::Segment(name) => (segment_id(name)?, None),
+                PlacedTarget::Undefined(name) => (
+                    SEGMENT_UNDEFINED,
+                    layout.unresolved.iter().position(|u| u == name),
+                ),
+            };
+            let kind = match relocation.kind {
+                RelocationKind::Word => RELOCATION_WORD,
+                RelocationKind::High => RELOCATION_HIGH,
+                RelocationKind::ZeroPage | RelocationKind::Low => RELOCATION_LOW,
+                RelocationKind::Decimal | RelocationKind::Relative => {
+                    return Err(Error::UnsupportedRelocation(relocation.address))
+                }
+            };
+            out.push(kind | id);
+            if let Some(index) = undefined_index {
+                out.extend(&(index as u16).to_le_bytes());
+            }
+            if relocation.kind == RelocationKind::High {
+                out.push(relocation.value.to_le_bytes()[0]);
+            }
+        }
+        out.push(0);
+    }
+
+    let exports: Vec<_> = layout.symbols.iter().filter(|s| s.exported).collect();
+    write_count(&mut out, exports.len())?;
+    for symbol in exports {
+        write_name(&mut out, &symbol.name);
+        out.push(segment_id(&symbol.segment)?);
+        out.extend(&symbol.address.to_le_bytes());
+    }
+
+    Ok(out)
+}
+
+fn write_count(out: &mut Vec<u8>, count: usize) -> Result<(), Error> {
+    let count = u16::try_from(count).map_err(|_| Error::TooLarge("symbol list"))?;
+    out.extend(&count.to_le_bytes());
+    Ok(())
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    out.extend(name.as_bytes());
+    out.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linker::{self, LinkerConfig};
+    use crate::{assemble_object, object::Object};
+
+    fn layout(objects: &[Object]) -> Layout {
+        let config: LinkerConfig = "ZEROPAGE: start = $0000, size = $0100;\nCODE: start = $1000, size = $1000;\nDATA: start = $2000, size = $0100;\n"
+            .parse()
+            .unwrap();
+        linker::layout(&config, objects, &[]).unwrap()
+    }
+
+    #[test]
+    fn write_success() {
+        let main = assemble_object(
+            "  .import print\n  .export start\nstart:\n  JMP print\n  LDA #<data\n  LDA #>data\n  .segment \"DATA\"\ndata:\n  JMP start\n",
+        )
+        .unwrap();
+        let result = write(&layout(&[main]), &Options::default()).unwrap();
+
+        let mut expected = vec![0x01, 0x00, b'o', b'6', b'5', 0x00, 0x00, 0x00];
+        expected.extend(&[0x00, 0x10, 0x07, 0x00]); // text
+        expected.extend(&[0x00, 0x20, 0x03, 0x00]); // data
+        expected.extend(&[0x00, 0x00, 0x00, 0x00]); // bss
+        expected.extend(&[0x00, 0x00, 0x00, 0x00]); // zero
+        expected.extend(&[0x00, 0x00]); // stack
+        expected.extend(&[0x08, 0x02, b's', b'f', b'o', b't', b'a', 0x00, 0x00]);
+        expected.extend(&[0x4C, 0x00, 0x00, 0xA9, 0x00, 0xA9, 0x20]);
+        expected.extend(&[0x4C, 0x00, 0x10]);
+        expected.extend(&[0x01, 0x00, b'p', b'r', b'i', b'n', b't', 0x00]);
+        // text: undefined word at $1001, low byte at $1004, high byte at $1006
+        expected.extend(&[0x02, 0x80, 0x00, 0x00, 0x03, 0x23, 0x02, 0x43, 0x00, 0x00]);
+        // data: word at $2001
+        expected.extend(&[0x02, 0x82, 0x00]);
+        expected.extend(&[
+            0x01, 0x00, b's', b't', b'a', b'r', b't', 0x00, 0x02, 0x00, 0x10,
+        ]);
+        assert_eq!(expected, result)
+    }
+
+    #[test]
+    fn undefined_high_byte() {
+        let object =
+            assemble_object("  .import print, other\n  JMP print\n  LDA #>other\n").unwrap();
+        let result = write(&layout(&[object]), &Options::default()).unwrap();
+        // Undefined references are sorted by name. The index of `other` comes before the low
+        // byte of its HIGH relocation.
+        let text_relocations = &result[result.len() - 13..result.len() - 3];
+        assert_eq!(
+            &[0x02, 0x80, 0x01, 0x00, 0x03, 0x40, 0x00, 0x00, 0x00, 0x00],
+            text_relocations
+        )
+    }
+
+    #[test]
+    fn long_relocation_distance() {
+        let mut source = "start:\n  JMP start\n".to_owned();
+        source.push_str(&"  RTS\n".repeat(300));
+        source.push_str("  JMP start\n");
+        let result = write(
+            &layout(&[assemble_object(&source).unwrap()]),
+            &Options::default(),
+        )
+        .unwrap();
+        // Second relocation is 303 bytes after the first: 255 (254) then 49.
+        let text_relocations = &result[result.len() - 9..result.len() - 3];
+        assert_eq!(&[0x02, 0x82, 0xFF, 0x31, 0x82, 0x00], text_relocations)
+    }
+
+    #[test]
+    fn unmapped_segment() {
+        let object = assemble_object("  .segment \"DATA\"\n  RTS\n").unwrap();
+        let options = Options {
+            data: vec![],
+            ..Options::default()
+        };
+        assert_eq!(
+            Err(Error::UnmappedSegment("DATA".to_owned())),
+            write(&layout(&[object]), &options)
+        )
+    }
+
+    #[test]
+    fn initialized_bss() {
+        let object = assemble_object("  .segment \"DATA\"\n  RTS\n").unwrap();
+        let options = Options {
+            data: vec![],
+            bss: vec!["DATA".to_owned()],
+            ..Options::default()
+        };
+        assert_eq!(
+            Err(Error::InitializedBss("DATA".to_owned())),
+            write(&layout(&[object]), &options)
+        )
+    }
+}
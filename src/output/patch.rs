@@ -0,0 +1,324 @@
+//! IPS and BPS patches against a base ROM image.
+//!
+//! [`apply`] lays the assembled segments over a copy of the base image. Mappings tie CPU
+//! addresses to file offsets, so an address can be mapped once per bank. [`ips`] and [`bps`] then
+//! describe the difference between the base image and the result.
+
+use crate::linker::Layout;
+
+const IPS_MAGIC: &[u8; 5] = b"PATCH";
+const IPS_EOF: &[u8; 3] = b"EOF";
+const IPS_MAX_OFFSET: usize = 0xFF_FFFF;
+const IPS_MAX_RECORD: usize = 0xFFFF;
+const BPS_MAGIC: &[u8; 4] = b"BPS1";
+const BPS_SOURCE_READ: usize = 0;
+const BPS_TARGET_READ: usize = 1;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("segment {segment} writes to {address:#06X}, which is not mapped in the base image")]
+    Unmapped { segment: String, address: u16 },
+    #[error("IPS patches can not change bytes past offset $FFFFFF")]
+    TooLarge,
+    #[error("IPS patches can not shrink the image")]
+    Truncated,
+}
+
+/// A range of CPU addresses and the offset in the base image it is stored at.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Mapping {
+    pub address: u16,
+    pub offset: usize,
+    pub size: usize,
+    /// Only segments in this list are mapped here. All of them are when it is empty.
+    pub segments: Vec<String>,
+}
+
+impl Mapping {
+    pub fn new(address: u16, offset: usize, size: usize) -> Self {
+        Mapping {
+            address,
+            offset,
+            size,
+            segments: vec![],
+        }
+    }
+
+    fn file_offset(&self, segment: &str, address: u16) -> Option<usize> {
+        let relative = (address as usize).checked_sub(self.address as usize)?;
+        let holds_segment = self.segments.is_empty() || self.segments.iter().any(|s| s == segment);
+        Some(self.offset + relative).filter(|_| relative < self.size && holds_segment)
+    }
+}
+
+/// The base image with every segment written over it.
+pub fn apply(base: &[u8], layout: &Layout, mappings: &[Mapping]) -> Result<Vec<u8>, Error> {
+    let mut image = base.to_vec();
+    for segment in &layout.segments {
+        for (idx, byte) in segment.bytes.iter().enumerate() {
+            let address = segment.start.wrapping_add(idx as u16);
+            let offset = mappings
+                .iter()
+                .find_map(|m| m.file_offset(&segment.name, address))
+                .filter(|offset| *offset < image.len())
+                .ok_or_else(|| Error::Unmapped {
+                    segment: segment.name.clone(),
+                    address,
+                })?;
+            image[offset] = *byte;
+        }
+    }
+    Ok(image)
+}
+
+/// Runs of bytes in `target` that differ from `base`, as offsets and lengths.
+fn differences(base: &[u8], target: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = vec![];
+    for (offset, byte) in target.iter().enumerate() {
+        if base.get(offset) == Some(byte) {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((start, length)) if *start + *length == offset => *length += 1,
+            _ => runs.push((offset, 1)),
+        }
+    }
+    runs
+}
+
+pub fn ips(base: &[u8], target: &[u8]) -> Result<Vec<u8>, Error> {
+    if target.len() < base.len() {
+        return Err(Error::Truncated);
+    }
+    let mut out = IPS_MAGIC.to_vec();
+    for (start, length) in differences(base, target) {
+        let mut offset = start;
+        let end = start + length;
+        while offset < end {
+            if offset > IPS_MAX_OFFSET {
+                return Err(Error::TooLarge);
+            }
+            // An offset that reads as "EOF" would end the patch, so such a record starts a byte
+            // earlier.
+            if (offset as u32).to_be_bytes()[1..] == IPS_EOF[..] {
+                offset -= 1;
+            }
+            let size = std::cmp::min(end - offset, IPS_MAX_RECORD);
+            out.extend(&(offset as u32).to_be_bytes()[1..]);
+            out.extend(&(size as u16).to_be_bytes());
+            out.extend(&target[offset..offset + size]);
+            offset += size;
+        }
+    }
+    out.extend(IPS_EOF);
+    Ok(out)
+}
+
+pub fn bps(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = BPS_MAGIC.to_vec();
+    number(&mut out, base.len());
+    number(&mut out, target.len());
+    number(&mut out, 0);
+    let mut offset = 0;
+    for (start, length) in differences(base, target) {
+        if start > offset {
+            number(&mut out, ((start - offset - 1) << 2) | BPS_SOURCE_READ);
+        }
+        number(&mut out, ((length - 1) << 2) | BPS_TARGET_READ);
+        out.extend(&target[start..start + length]);
+        offset = start + length;
+    }
+    if target.len() > offset {
+        number(
+            &mut out,
+            ((target.len() - offset - 1) << 2) | BPS_SOURCE_READ,
+        );
+    }
+    out.extend(&crc32(base).to_le_bytes());
+    out.extend(&crc32(target).to_le_bytes());
+    let checksum = crc32(&out);
+    out.extend(&checksum.to_le_bytes());
+    out
+}
+
+/// BPS variable-length numbers, seven bits at a time with the last byte marked.
+fn number(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let bits = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | bits);
+            return;
+        }
+        out.push(bits);
+        value -= 1;
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::LinkerConfig;
+
+    fn apply_ips(base: &[u8], patch: &[u8]) -> Vec<u8> {
+        assert_eq!(IPS_MAGIC, &patch[..5]);
+        let mut image = base.to_vec();
+        let mut rest = &patch[5..];
+        while rest != IPS_EOF {
+            let offset = u32::from_be_bytes([0, rest[0], rest[1], rest[2]]) as usize;
+            let size = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+            if image.len() < offset + size {
+                image.resize(offset + size, 0);
+            }
+            image[offset..offset + size].copy_from_slice(&rest[5..5 + size]);
+            rest = &rest[5 + size..];
+        }
+        image
+    }
+
+    fn read_number(rest: &mut &[u8]) -> usize {
+        let (mut value, mut shift) = (0, 1);
+        loop {
+            let byte = rest[0];
+            *rest = &rest[1..];
+            value += (byte as usize & 0x7F) * shift;
+            if byte & 0x80 != 0 {
+                return value;
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+
+    fn apply_bps(base: &[u8], patch: &[u8]) -> Vec<u8> {
+        assert_eq!(BPS_MAGIC, &patch[..4]);
+        let (body, footer) = patch.split_at(patch.len() - 12);
+        let crc = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        assert_eq!(crc32(base), crc(&footer[..4]));
+        assert_eq!(crc32(&patch[..patch.len() - 4]), crc(&footer[8..]));
+
+        let mut rest = &body[4..];
+        assert_eq!(base.len(), read_number(&mut rest));
+        let target_size = read_number(&mut rest);
+        assert_eq!(0, read_number(&mut rest));
+        let mut target = vec![];
+        while !rest.is_empty() {
+            let action = read_number(&mut rest);
+            let length = (action >> 2) + 1;
+            match action & 3 {
+                BPS_SOURCE_READ => {
+                    let offset = target.len();
+                    target.extend(&base[offset..offset + length])
+                }
+                BPS_TARGET_READ => {
+                    target.extend(&rest[..length]);
+                    rest = &rest[length..];
+                }
+                command => panic!("Unexpected command {}", command),
+            }
+        }
+        assert_eq!(target_size, target.len());
+        assert_eq!(crc32(&target), crc(&footer[4..8]));
+        target
+    }
+
+    fn patched() -> (Vec<u8>, Vec<u8>) {
+        let config: LinkerConfig = "CODE: start = $8000, size = $10;".parse().unwrap();
+        let layout = assemble_layout(
+            "  RTS\n  .org $8100\n  JMP $8000\n  .org $FFFC\n  JMP $8100\n",
+            &config,
+        )
+        .unwrap();
+        let base: Vec<u8> = (0..0x8000).map(|i| (i % 251) as u8).collect();
+        let mappings = vec![Mapping::new(0x8000, 0, 0x8000)];
+        let target = apply(&base, &layout, &mappings).unwrap();
+        (base, target)
+    }
+
+    #[test]
+    fn apply_success() {
+        let (base, target) = patched();
+        assert_eq!(base.len(), target.len());
+        assert_eq!(0x60, target[0]);
+        assert_eq!(&[0x4C, 0x00, 0x80], &target[0x100..0x103]);
+        assert_eq!(&[0x4C, 0x00, 0x81], &target[0x7FFC..0x7FFF]);
+        assert_eq!(base[1..0x100], target[1..0x100])
+    }
+
+    #[test]
+    fn unmapped() {
+        let config: LinkerConfig = "CODE: start = $8000, size = $10;".parse().unwrap();
+        let layout = assemble_layout("  RTS\n  .org $7FFF\n  RTS\n", &config).unwrap();
+        let mappings = vec![Mapping::new(0x8000, 0, 0x8000)];
+        assert_eq!(
+            Err(Error::Unmapped {
+                segment: "ORG_7FFF".to_owned(),
+                address: 0x7FFF
+            }),
+            apply(&[0; 0x8000], &layout, &mappings)
+        )
+    }
+
+    #[test]
+    fn banked_mapping() {
+        let config: LinkerConfig =
+            "BANK0: start = $8000, size = $10, bank = 0;\nBANK1: start = $8000, size = $10, bank = 1;"
+                .parse()
+                .unwrap();
+        let layout = assemble_layout("  .segment \"BANK1\"\n  RTS\n", &config).unwrap();
+        let mappings = vec![
+            Mapping {
+                segments: vec!["BANK0".to_owned()],
+                ..Mapping::new(0x8000, 0x10, 0x4000)
+            },
+            Mapping {
+                segments: vec!["BANK1".to_owned()],
+                ..Mapping::new(0x8000, 0x4010, 0x4000)
+            },
+        ];
+        let target = apply(&[0; 0x8010], &layout, &mappings).unwrap();
+        assert_eq!(0x00, target[0x10]);
+        assert_eq!(0x60, target[0x4010])
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let (base, target) = patched();
+        let patch = ips(&base, &target).unwrap();
+        assert_eq!(target, apply_ips(&base, &patch));
+        assert_eq!(5 + (5 + 1) + (5 + 3) + (5 + 3) + 3, patch.len())
+    }
+
+    #[test]
+    fn ips_eof_offset() {
+        let base = vec![0; 0x454F48];
+        let mut target = base.clone();
+        target[0x454F46] = 1;
+        let patch = ips(&base, &target).unwrap();
+        assert_eq!(&[0x45, 0x4F, 0x45, 0x00, 0x02, 0x00, 0x01], &patch[5..12]);
+        assert_eq!(target, apply_ips(&base, &patch))
+    }
+
+    #[test]
+    fn bps_round_trip() {
+        let (base, target) = patched();
+        assert_eq!(target, apply_bps(&base, &bps(&base, &target)));
+        let mut longer = target.clone();
+        longer.extend(&[1, 2, 3]);
+        assert_eq!(longer, apply_bps(&base, &bps(&base, &longer)))
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"))
+    }
+}
@@ -0,0 +1,76 @@
+//! Commodore PRG files: a little-endian load address followed by the program.
+//!
+//! The file covers every non-empty segment from the lowest address to the end of the highest
+//! one, with the gaps in between filled, as the KERNAL loads it as a single block.
+
+use crate::linker::Layout;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("there are no bytes to write")]
+    Empty,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Options {
+    /// Value for the bytes between segments.
+    pub fill: u8,
+}
+
+pub fn write(layout: &Layout, options: &Options) -> Result<Vec<u8>, Error> {
+    let segments: Vec<_> = layout
+        .segments
+        .iter()
+        .filter(|s| !s.bytes.is_empty())
+        .collect();
+    let start = segments.iter().map(|s| s.start).min().ok_or(Error::Empty)?;
+    let end = segments
+        .iter()
+        .map(|s| s.start as usize + s.bytes.len())
+        .max()
+        .ok_or(Error::Empty)?;
+
+    let mut program = vec![options.fill; end - start as usize];
+    for segment in segments {
+        let offset = (segment.start - start) as usize;
+        program[offset..offset + segment.bytes.len()].copy_from_slice(&segment.bytes);
+    }
+    let mut out = start.to_le_bytes().to_vec();
+    out.extend(program);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::LinkerConfig;
+
+    #[test]
+    fn basic_stub() {
+        let config: LinkerConfig = "CODE: start = $0801, size = $1000;".parse().unwrap();
+        let layout = assemble_layout("  .basicstub start\nstart:\n  JMP start\n", &config).unwrap();
+        let mut expected = vec![0x01, 0x08];
+        expected.extend(&[0x0C, 0x08, 0x0A, 0x00, 0x9E]);
+        expected.extend(b" 2062");
+        expected.extend(&[0x00, 0x00, 0x00, 0x4C, 0x0E, 0x08]);
+        assert_eq!(Ok(expected), write(&layout, &Options::default()))
+    }
+
+    #[test]
+    fn gaps_filled() {
+        let config: LinkerConfig = "CODE: start = $C000, size = $1000;".parse().unwrap();
+        let layout = assemble_layout("  RTS\n  .org $C003\n  RTS\n", &config).unwrap();
+        let options = Options { fill: 0xEA };
+        assert_eq!(
+            Ok(vec![0x00, 0xC0, 0x60, 0xEA, 0xEA, 0x60]),
+            write(&layout, &options)
+        )
+    }
+
+    #[test]
+    fn empty() {
+        let layout = assemble_layout("start:\n", &LinkerConfig::default()).unwrap();
+        assert_eq!(Err(Error::Empty), write(&layout, &Options::default()))
+    }
+}
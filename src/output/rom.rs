@@ -0,0 +1,124 @@
+//! Fixed-size memory images, e.g. for programming an EEPROM.
+//!
+//! The image covers `size` bytes from `start`. Bytes no segment writes to are set to the fill
+//! byte, and every byte a segment does write has to lie inside the image.
+
+use crate::linker::Layout;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("the image runs past $FFFF")]
+    TooLarge,
+    #[error("segment {segment} writes to {address:#06X}, outside the image")]
+    OutsideImage { segment: String, address: u16 },
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    pub start: u16,
+    pub size: usize,
+    pub fill: u8,
+}
+
+impl Default for Options {
+    /// A 32K ROM at `$8000`, filled with `$FF` like an erased EEPROM.
+    fn default() -> Self {
+        Options {
+            start: 0x8000,
+            size: 0x8000,
+            fill: 0xFF,
+        }
+    }
+}
+
+pub fn write(layout: &Layout, options: &Options) -> Result<Vec<u8>, Error> {
+    let start = options.start as usize;
+    if start + options.size > 0x10000 {
+        return Err(Error::TooLarge);
+    }
+    let mut image = vec![options.fill; options.size];
+    for segment in &layout.segments {
+        for (idx, byte) in segment.bytes.iter().enumerate() {
+            let address = segment.start as usize + idx;
+            if address < start || address >= start + options.size {
+                return Err(Error::OutsideImage {
+                    segment: segment.name.clone(),
+                    address: address as u16,
+                });
+            }
+            image[address - start] = *byte;
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linker::LinkerConfig;
+    use crate::{assemble_layout, linker, Error as AssemblerError};
+
+    fn config() -> LinkerConfig {
+        "CODE: start = $8000, size = $7FFA;".parse().unwrap()
+    }
+
+    #[test]
+    fn write_success() {
+        let layout = assemble_layout(
+            "  .vectors nmi, reset, irq\nreset:\n  JMP reset\nnmi:\nirq:\n  RTS\n",
+            &config(),
+        )
+        .unwrap();
+        let result = write(&layout, &Options::default()).unwrap();
+        assert_eq!(0x8000, result.len());
+        assert_eq!(&[0x4C, 0x00, 0x80, 0x60, 0xFF], &result[..5]);
+        assert_eq!(&[0x03, 0x80, 0x00, 0x80, 0x03, 0x80], &result[0x7FFA..])
+    }
+
+    #[test]
+    fn fill() {
+        let layout = assemble_layout("  RTS\n", &config()).unwrap();
+        let options = Options {
+            fill: 0xEA,
+            ..Options::default()
+        };
+        let result = write(&layout, &options).unwrap();
+        assert_eq!(&[0x60, 0xEA], &result[..2]);
+        assert_eq!(0xEA, result[0x7FFF])
+    }
+
+    #[test]
+    fn outside_image() {
+        let layout = assemble_layout("  RTS\n  .org $7FFF\n  RTS\n", &config()).unwrap();
+        assert_eq!(
+            Err(Error::OutsideImage {
+                segment: "ORG_7FFF".to_owned(),
+                address: 0x7FFF
+            }),
+            write(&layout, &Options::default())
+        )
+    }
+
+    #[test]
+    fn too_large() {
+        let options = Options {
+            size: 0x8001,
+            ..Options::default()
+        };
+        let layout = assemble_layout("  RTS\n", &config()).unwrap();
+        assert_eq!(Err(Error::TooLarge), write(&layout, &options))
+    }
+
+    #[test]
+    fn vectors_overlap() {
+        let config: LinkerConfig = "CODE: start = $8000, size = $8000;".parse().unwrap();
+        let result = assemble_layout(
+            "  .vectors start, start, start\n  .org $FFFB\nstart:\n  RTS\n",
+            &config,
+        );
+        assert!(matches!(
+            result,
+            Err(AssemblerError::LinkError(linker::Error::Overlap(_, _, _)))
+        ))
+    }
+}
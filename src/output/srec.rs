@@ -0,0 +1,213 @@
+//! Motorola S-records.
+//!
+//! The address width is the smallest that fits the highest address in use, including the entry
+//! point: S1/S9 up to 16 bits, S2/S8 up to 24 bits and S3/S7 above that. Like Intel HEX, every
+//! non-empty segment is written at its own address without padding the gaps.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use crate::linker::Layout;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("record length must be between 1 and {0}")]
+    RecordLength(u8),
+    #[error("segment {0} does not fit below 4G at the base address")]
+    TooLarge(String),
+    #[error("header does not fit in a record")]
+    HeaderTooLong,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    /// Data bytes per record.
+    pub record_length: u8,
+    /// Added to every address, including the entry point.
+    pub base_address: u32,
+    /// Contents of the S0 header record.
+    pub header: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            record_length: 32,
+            base_address: 0,
+            header: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Width {
+    S19,
+    S28,
+    S37,
+}
+
+impl Width {
+    fn address_len(self) -> usize {
+        match self {
+            Width::S19 => 2,
+            Width::S28 => 3,
+            Width::S37 => 4,
+        }
+    }
+
+    fn data_type(self) -> u8 {
+        match self {
+            Width::S19 => 1,
+            Width::S28 => 2,
+            Width::S37 => 3,
+        }
+    }
+
+    fn start_type(self) -> u8 {
+        match self {
+            Width::S19 => 9,
+            Width::S28 => 8,
+            Width::S37 => 7,
+        }
+    }
+}
+
+pub fn write(layout: &Layout, options: &Options) -> Result<String, Error> {
+    let mut segments: Vec<_> = layout
+        .segments
+        .iter()
+        .filter(|s| !s.bytes.is_empty())
+        .collect();
+    segments.sort_by_key(|s| s.start);
+
+    let mut regions = vec![];
+    for segment in segments {
+        let too_large = || Error::TooLarge(segment.name.clone());
+        let start = options
+            .base_address
+            .checked_add(segment.start as u32)
+            .ok_or_else(too_large)?;
+        let last = u32::try_from(start as u64 + segment.bytes.len() as u64 - 1)
+            .map_err(|_| too_large())?;
+        regions.push((start, last, &segment.bytes));
+    }
+    let entry = layout
+        .entry
+        .map(|e| options.base_address.wrapping_add(e as u32));
+    let highest = regions
+        .iter()
+        .map(|(_, last, _)| *last)
+        .chain(entry)
+        .max()
+        .unwrap_or(0);
+    let width = match highest {
+        0..=0xFFFF => Width::S19,
+        0x1_0000..=0xFF_FFFF => Width::S28,
+        _ => Width::S37,
+    };
+    let max_length = 0xFF - 1 - width.address_len() as u8;
+    if options.record_length == 0 || options.record_length > max_length {
+        return Err(Error::RecordLength(max_length));
+    }
+    if options.header.len() > 0xFF - 3 {
+        return Err(Error::HeaderTooLong);
+    }
+
+    let mut out = String::new();
+    record(&mut out, 0, 0, 2, options.header.as_bytes());
+    let mut count = 0u32;
+    for (start, _, bytes) in regions {
+        for (idx, data) in bytes.chunks(options.record_length as usize).enumerate() {
+            let address = start + (idx * options.record_length as usize) as u32;
+            record(
+                &mut out,
+                width.data_type(),
+                address,
+                width.address_len(),
+                data,
+            );
+            count += 1;
+        }
+    }
+    match count {
+        0..=0xFFFF => record(&mut out, 5, count, 2, &[]),
+        _ => record(&mut out, 6, count, 3, &[]),
+    }
+    record(
+        &mut out,
+        width.start_type(),
+        entry.unwrap_or(0),
+        width.address_len(),
+        &[],
+    );
+    Ok(out)
+}
+
+fn record(out: &mut String, kind: u8, address: u32, address_len: usize, data: &[u8]) {
+    let mut bytes = vec![(address_len + data.len() + 1) as u8];
+    bytes.extend(&address.to_be_bytes()[4 - address_len..]);
+    bytes.extend(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    write!(out, "S{}", kind).unwrap();
+    for byte in bytes.iter().chain(&[checksum]) {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::LinkerConfig;
+
+    fn layout() -> Layout {
+        let config: LinkerConfig = "CODE: start = $8000, size = $0100;".parse().unwrap();
+        assemble_layout(
+            "  RTS\n  .entry start\nstart:\n  JMP start\n  .org $FFFC\n  JMP start\n",
+            &config,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn s19_success() {
+        let options = Options {
+            header: "HDR".to_owned(),
+            ..Options::default()
+        };
+        let expected =
+            "S00600004844521B\nS1078000604C01804B\nS106FFFC4C018031\nS5030002FA\nS90380017B\n";
+        assert_eq!(Ok(expected.to_owned()), write(&layout(), &options))
+    }
+
+    #[test]
+    fn s28_success() {
+        let options = Options {
+            record_length: 2,
+            base_address: 0x1_0000,
+            ..Options::default()
+        };
+        let expected = "S0030000FC\nS206018000604CCC\nS2060180020180F5\nS20601FFFC4C01B0\nS20501FFFE807C\nS5030004F8\nS80401800179\n";
+        assert_eq!(Ok(expected.to_owned()), write(&layout(), &options))
+    }
+
+    #[test]
+    fn s37_success() {
+        let options = Options {
+            base_address: 0x100_0000,
+            ..Options::default()
+        };
+        let expected = "S0030000FC\nS30901008000604C018048\nS3080100FFFC4C01802E\nS5030002FA\nS7050100800178\n";
+        assert_eq!(Ok(expected.to_owned()), write(&layout(), &options))
+    }
+
+    #[test]
+    fn record_length() {
+        let options = Options {
+            record_length: 253,
+            ..Options::default()
+        };
+        assert_eq!(Err(Error::RecordLength(252)), write(&layout(), &options))
+    }
+}
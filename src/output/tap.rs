@@ -0,0 +1,181 @@
+//! Commodore 64 tape images (TAP version 1), encoded the way the KERNAL saves a program.
+//!
+//! The program is written as a header block with the name and the addresses, followed by a data
+//! block. Each block is recorded twice after a leader of short pulses, with the countdown bytes
+//! the KERNAL uses to tell the copies apart and an XOR checksum.
+
+const MAGIC: &[u8; 12] = b"C64-TAPE-RAW";
+const VERSION: u8 = 1;
+const SHORT: u8 = 0x30;
+const MEDIUM: u8 = 0x42;
+const LONG: u8 = 0x56;
+const HEADER_LEADER: usize = 0x6A00;
+const DATA_LEADER: usize = 0x1A00;
+const GAP: usize = 79;
+const TRAILER: usize = 78;
+const HEADER_SIZE: usize = 192;
+const NAME_LENGTH: usize = 16;
+/// A program that is always loaded at the address in its header.
+const FILE_TYPE_ABSOLUTE: u8 = 0x03;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("name {0:?} is longer than 16 characters or not ASCII")]
+    InvalidName(String),
+    #[error("the program has no load address")]
+    MissingLoadAddress,
+    #[error("the program runs past $FFFF")]
+    TooLarge,
+}
+
+/// Writes `prg`, which starts with the load address as written by
+/// [`prg::write`](super::prg::write), as a tape named `name`.
+pub fn write(name: &str, prg: &[u8]) -> Result<Vec<u8>, Error> {
+    if name.len() > NAME_LENGTH || !name.is_ascii() {
+        return Err(Error::InvalidName(name.to_owned()));
+    }
+    if prg.len() < 2 {
+        return Err(Error::MissingLoadAddress);
+    }
+    let (address, program) = prg.split_at(2);
+    let start = u16::from_le_bytes([address[0], address[1]]);
+    let end = start as usize + program.len();
+    if end > 0xFFFF {
+        return Err(Error::TooLarge);
+    }
+
+    let mut header = vec![b' '; HEADER_SIZE];
+    header[0] = FILE_TYPE_ABSOLUTE;
+    header[1..3].copy_from_slice(&start.to_le_bytes());
+    header[3..5].copy_from_slice(&(end as u16).to_le_bytes());
+    header[5..5 + name.len()].copy_from_slice(name.as_bytes());
+
+    let mut pulses = vec![];
+    block(&mut pulses, HEADER_LEADER, &header);
+    block(&mut pulses, DATA_LEADER, program);
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend(&[0; 3]);
+    out.extend(&(pulses.len() as u32).to_le_bytes());
+    out.extend(pulses);
+    Ok(out)
+}
+
+/// Both copies of a block.
+fn block(pulses: &mut Vec<u8>, leader: usize, data: &[u8]) {
+    let checksum = data.iter().fold(0, |sum, byte| sum ^ byte);
+    pulses.extend(std::iter::repeat_n(SHORT, leader));
+    for (countdown, trailer) in [(0x89, GAP), (0x09, TRAILER)] {
+        for count in (0..9).map(|n| countdown - n) {
+            byte(pulses, count);
+        }
+        for &b in data {
+            byte(pulses, b);
+        }
+        byte(pulses, checksum);
+        pulses.extend(&[LONG, SHORT]);
+        pulses.extend(std::iter::repeat_n(SHORT, trailer));
+    }
+}
+
+/// A byte marker, the bits from the lowest up, and an odd parity bit.
+fn byte(pulses: &mut Vec<u8>, byte: u8) {
+    pulses.extend(&[LONG, MEDIUM]);
+    let parity = 1 ^ (byte.count_ones() as u8 & 1);
+    for bit in (0..8)
+        .map(|n| (byte >> n) & 1)
+        .chain(std::iter::once(parity))
+    {
+        match bit {
+            0 => pulses.extend(&[SHORT, MEDIUM]),
+            _ => pulses.extend(&[MEDIUM, SHORT]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the bytes of every block copy back from the pulses, checking the parity bits.
+    fn blocks(tap: &[u8]) -> Vec<Vec<u8>> {
+        assert_eq!(MAGIC, &tap[..12]);
+        let size = u32::from_le_bytes([tap[16], tap[17], tap[18], tap[19]]) as usize;
+        let pulses = &tap[20..];
+        assert_eq!(size, pulses.len());
+        let kinds: Vec<char> = pulses
+            .iter()
+            .map(|&p| match p {
+                0..=0x38 => 'S',
+                0x39..=0x4C => 'M',
+                _ => 'L',
+            })
+            .collect();
+
+        let mut blocks = vec![];
+        let mut current = vec![];
+        let mut idx = 0;
+        while idx + 1 < kinds.len() {
+            match (kinds[idx], kinds[idx + 1]) {
+                ('L', 'M') => {
+                    let bits: Vec<u8> = kinds[idx + 2..idx + 20]
+                        .chunks(2)
+                        .map(|pair| match pair {
+                            ['S', 'M'] => 0,
+                            ['M', 'S'] => 1,
+                            _ => panic!("Invalid bit {:?}", pair),
+                        })
+                        .collect();
+                    let byte = bits[..8].iter().rev().fold(0, |b, bit| (b << 1) | bit);
+                    assert_eq!(1, bits.iter().sum::<u8>() & 1);
+                    current.push(byte);
+                    idx += 20;
+                }
+                ('L', 'S') => {
+                    blocks.push(std::mem::take(&mut current));
+                    idx += 2;
+                }
+                _ => idx += 1,
+            }
+        }
+        blocks
+    }
+
+    fn check(block: &[u8], countdown: u8) -> &[u8] {
+        let expected: Vec<u8> = (0..9).map(|n| countdown - n).collect();
+        assert_eq!(expected, &block[..9]);
+        let (data, checksum) = block[9..].split_at(block.len() - 10);
+        assert_eq!(data.iter().fold(0, |sum, b| sum ^ b), checksum[0]);
+        data
+    }
+
+    #[test]
+    fn write_success() {
+        let program: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut prg = vec![0x00, 0xC0];
+        prg.extend(&program);
+        let tap = write("HELLO", &prg).unwrap();
+        let blocks = blocks(&tap);
+        assert_eq!(4, blocks.len());
+
+        let header = check(&blocks[0], 0x89);
+        assert_eq!(header, check(&blocks[1], 0x09));
+        assert_eq!(HEADER_SIZE, header.len());
+        assert_eq!(&[0x03, 0x00, 0xC0, 0x2C, 0xC1], &header[..5]);
+        assert_eq!(b"HELLO           ", &header[5..21]);
+
+        assert_eq!(&program[..], check(&blocks[2], 0x89));
+        assert_eq!(&program[..], check(&blocks[3], 0x09))
+    }
+
+    #[test]
+    fn too_large() {
+        assert_eq!(Err(Error::TooLarge), write("", &[0xFF, 0xFF, 0x00]))
+    }
+
+    #[test]
+    fn missing_load_address() {
+        assert_eq!(Err(Error::MissingLoadAddress), write("", &[0x01]))
+    }
+}
@@ -0,0 +1,223 @@
+//! Cassette audio as 8-bit mono WAV files, to be played into a tape interface.
+//!
+//! Two encodings are supported:
+//! - Kansas City Standard at 300 baud: a `0` is four cycles of 1200 Hz and a `1` eight cycles of
+//!   2400 Hz. Each byte is framed by a start bit and two stop bits, lowest bit first.
+//! - The Apple I and Apple II cassette format: a 770 Hz leader, a sync bit, then each bit as one
+//!   cycle of 2000 Hz (`0`) or 1000 Hz (`1`), highest bit first. It ends with the checksum the
+//!   Apple II monitor expects; the Apple I ignores it.
+
+const KCS_ZERO: u32 = 1200;
+const KCS_ONE: u32 = 2400;
+const APPLE_LEADER: u32 = 770;
+const APPLE_SYNC: [u32; 2] = [2500, 2000];
+const APPLE_ZERO: u32 = 2000;
+const APPLE_ONE: u32 = 1000;
+const HIGH: u8 = 0xC0;
+const LOW: u8 = 0x40;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Encoding {
+    KansasCity,
+    Apple,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Options {
+    pub encoding: Encoding,
+    pub sample_rate: u32,
+    /// Length of the leader tone in milliseconds.
+    pub leader_ms: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            encoding: Encoding::KansasCity,
+            sample_rate: 44100,
+            leader_ms: 5000,
+        }
+    }
+}
+
+/// Square wave samples, built from half cycles.
+struct Wave {
+    sample_rate: u32,
+    samples: Vec<u8>,
+    /// End of the last half cycle, in samples.
+    time: f64,
+    high: bool,
+}
+
+impl Wave {
+    fn half_cycle(&mut self, frequency: u32) {
+        self.time += self.sample_rate as f64 / (2 * frequency) as f64;
+        let level = if self.high { HIGH } else { LOW };
+        let end = self.time.round() as usize;
+        self.samples.resize(end.max(self.samples.len()), level);
+        self.high = !self.high;
+    }
+
+    fn cycles(&mut self, frequency: u32, count: u32) {
+        for _ in 0..2 * count {
+            self.half_cycle(frequency);
+        }
+    }
+
+    fn kcs_bit(&mut self, bit: bool) {
+        match bit {
+            false => self.cycles(KCS_ZERO, 4),
+            true => self.cycles(KCS_ONE, 8),
+        }
+    }
+
+    fn apple_bit(&mut self, bit: bool) {
+        self.cycles(if bit { APPLE_ONE } else { APPLE_ZERO }, 1)
+    }
+}
+
+pub fn write(bytes: &[u8], options: &Options) -> Vec<u8> {
+    let mut wave = Wave {
+        sample_rate: options.sample_rate,
+        samples: vec![],
+        time: 0.0,
+        high: true,
+    };
+    match options.encoding {
+        Encoding::KansasCity => {
+            wave.cycles(KCS_ONE, KCS_ONE * options.leader_ms / 1000);
+            for byte in bytes {
+                wave.kcs_bit(false);
+                for bit in 0..8 {
+                    wave.kcs_bit(byte & (1 << bit) != 0);
+                }
+                wave.kcs_bit(true);
+                wave.kcs_bit(true);
+            }
+        }
+        Encoding::Apple => {
+            wave.cycles(APPLE_LEADER, APPLE_LEADER * options.leader_ms / 1000);
+            for frequency in &APPLE_SYNC {
+                wave.half_cycle(*frequency);
+            }
+            let checksum = bytes.iter().fold(0xFF, |sum, byte| sum ^ byte);
+            for byte in bytes.iter().chain(std::iter::once(&checksum)) {
+                for bit in (0..8).rev() {
+                    wave.apple_bit(byte & (1 << bit) != 0);
+                }
+            }
+            wave.cycles(APPLE_LEADER, 1);
+        }
+    }
+
+    let data = wave.samples;
+    let mut out = b"RIFF".to_vec();
+    out.extend(&(36 + data.len() as u32).to_le_bytes());
+    out.extend(b"WAVEfmt ");
+    out.extend(&16u32.to_le_bytes());
+    out.extend(&1u16.to_le_bytes());
+    out.extend(&1u16.to_le_bytes());
+    out.extend(&options.sample_rate.to_le_bytes());
+    out.extend(&options.sample_rate.to_le_bytes());
+    out.extend(&1u16.to_le_bytes());
+    out.extend(&8u16.to_le_bytes());
+    out.extend(b"data");
+    out.extend(&(data.len() as u32).to_le_bytes());
+    out.extend(data);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The length of every half cycle in samples.
+    fn half_cycles(wav: &[u8]) -> (u32, Vec<usize>) {
+        assert_eq!(b"RIFF", &wav[..4]);
+        assert_eq!(b"WAVEfmt ", &wav[8..16]);
+        let sample_rate = u32::from_le_bytes([wav[24], wav[25], wav[26], wav[27]]);
+        assert_eq!(b"data", &wav[36..40]);
+        let size = u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize;
+        let samples = &wav[44..];
+        assert_eq!(size, samples.len());
+
+        let mut lengths = vec![];
+        let mut length = 0;
+        for pair in samples.windows(2) {
+            length += 1;
+            if (pair[0] >= 0x80) != (pair[1] >= 0x80) {
+                lengths.push(length);
+                length = 0;
+            }
+        }
+        lengths.push(length + 1);
+        (sample_rate, lengths)
+    }
+
+    fn decode_kcs(wav: &[u8]) -> Vec<u8> {
+        let (sample_rate, lengths) = half_cycles(wav);
+        let threshold = sample_rate as usize / (KCS_ZERO + KCS_ONE) as usize;
+        let mut bits = vec![];
+        let mut idx = 0;
+        while idx < lengths.len() {
+            if lengths[idx] > threshold {
+                bits.push(0);
+                idx += 8;
+            } else {
+                bits.push(1);
+                idx += 16;
+            }
+        }
+
+        let mut bytes = vec![];
+        let mut idx = 0;
+        while let Some(start) = bits[idx..].iter().position(|b| *b == 0) {
+            let frame = &bits[idx + start..idx + start + 11];
+            assert_eq!(&[1, 1], &frame[9..]);
+            bytes.push(frame[1..9].iter().rev().fold(0, |b, bit| (b << 1) | bit));
+            idx += start + 11;
+        }
+        bytes
+    }
+
+    fn decode_apple(wav: &[u8]) -> Vec<u8> {
+        let (sample_rate, lengths) = half_cycles(wav);
+        let rate = sample_rate as usize;
+        let leader = (rate / (2 * APPLE_LEADER) as usize + rate / (2 * APPLE_ONE) as usize) / 2;
+        let one = (rate / APPLE_ONE as usize + rate / APPLE_ZERO as usize) / 2;
+        let start = lengths.iter().position(|l| *l < leader).unwrap();
+        let bits: Vec<u8> = lengths[start + 2..lengths.len() - 2]
+            .chunks(2)
+            .map(|pair| (pair[0] + pair[1] > one) as u8)
+            .collect();
+        bits.chunks(8)
+            .map(|bits| bits.iter().fold(0, |b, bit| (b << 1) | bit))
+            .collect()
+    }
+
+    #[test]
+    fn kansas_city() {
+        let bytes: Vec<u8> = vec![0x00, 0xFF, 0xA9, 0x55, 0x4C, 0x00, 0x03];
+        let options = Options {
+            leader_ms: 100,
+            ..Options::default()
+        };
+        let wav = write(&bytes, &options);
+        assert_eq!(bytes, decode_kcs(&wav))
+    }
+
+    #[test]
+    fn apple() {
+        let bytes: Vec<u8> = vec![0x00, 0xFF, 0xA9, 0x55, 0x4C, 0x00, 0x03];
+        let options = Options {
+            encoding: Encoding::Apple,
+            sample_rate: 22050,
+            leader_ms: 100,
+        };
+        let wav = write(&bytes, &options);
+        let decoded = decode_apple(&wav);
+        assert_eq!(&bytes[..], &decoded[..bytes.len()]);
+        let checksum = bytes.iter().fold(0xFF, |sum, byte| sum ^ byte);
+        assert_eq!(&[checksum], &decoded[bytes.len()..])
+    }
+}
@@ -0,0 +1,105 @@
+//! Atari 8-bit DOS binary load files (XEX).
+//!
+//! Every non-empty segment, `.org` fragments included, becomes a load segment. The address of
+//! each `.init` label is stored in INITAD right after the segment that holds it, so DOS calls
+//! it as soon as its code is loaded. The `.entry` label goes into RUNAD at the end of the file.
+
+use std::convert::TryFrom;
+
+use crate::linker::Layout;
+
+const HEADER: [u8; 2] = [0xFF, 0xFF];
+const RUNAD: u16 = 0x02E0;
+const INITAD: u16 = 0x02E2;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("there are no bytes to write")]
+    Empty,
+    #[error("the load segment at {0:#06X} runs past $FFFF")]
+    PastEndOfMemory(u16),
+}
+
+pub fn write(layout: &Layout) -> Result<Vec<u8>, Error> {
+    let segments: Vec<_> = layout
+        .segments
+        .iter()
+        .filter(|s| !s.bytes.is_empty())
+        .collect();
+    if segments.is_empty() {
+        return Err(Error::Empty);
+    }
+
+    let mut out = HEADER.to_vec();
+    let mut written = vec![false; layout.inits.len()];
+    for segment in segments {
+        load_segment(&mut out, segment.start, &segment.bytes)?;
+        let end = segment.start as usize + segment.bytes.len();
+        for (init, written) in layout.inits.iter().zip(written.iter_mut()) {
+            if !*written && *init >= segment.start && (*init as usize) < end {
+                load_segment(&mut out, INITAD, &init.to_le_bytes())?;
+                *written = true;
+            }
+        }
+    }
+    for (init, written) in layout.inits.iter().zip(written) {
+        if !written {
+            load_segment(&mut out, INITAD, &init.to_le_bytes())?;
+        }
+    }
+    if let Some(entry) = layout.entry {
+        load_segment(&mut out, RUNAD, &entry.to_le_bytes())?;
+    }
+    Ok(out)
+}
+
+fn load_segment(out: &mut Vec<u8>, start: u16, bytes: &[u8]) -> Result<(), Error> {
+    let end = start as usize + bytes.len() - 1;
+    let end = u16::try_from(end).map_err(|_| Error::PastEndOfMemory(start))?;
+    out.extend(&start.to_le_bytes());
+    out.extend(&end.to_le_bytes());
+    out.extend(bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::LinkerConfig;
+
+    #[test]
+    fn write_success() {
+        let config: LinkerConfig = "CODE: start = $2000, size = $1000;".parse().unwrap();
+        let layout = assemble_layout(
+            "  .run start\n  .init setup\nsetup:\n  RTS\n  .org $3000\nstart:\n  JMP start\n",
+            &config,
+        )
+        .unwrap();
+        let expected = vec![
+            0xFF, 0xFF, // header
+            0x00, 0x20, 0x00, 0x20, 0x60, // CODE
+            0xE2, 0x02, 0xE3, 0x02, 0x00, 0x20, // INITAD
+            0x00, 0x30, 0x02, 0x30, 0x4C, 0x00, 0x30, // ORG_3000
+            0xE0, 0x02, 0xE1, 0x02, 0x00, 0x30, // RUNAD
+        ];
+        assert_eq!(Ok(expected), write(&layout))
+    }
+
+    #[test]
+    fn whole_address_space() {
+        let mut layout = assemble_layout("  RTS\n", &LinkerConfig::default()).unwrap();
+        layout.segments[0].bytes = vec![0x60; 0x10000];
+        let result = write(&layout).unwrap();
+        assert_eq!(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF], &result[..6]);
+
+        layout.segments[0].start = 0x0001;
+        assert_eq!(Err(Error::PastEndOfMemory(0x0001)), write(&layout))
+    }
+
+    #[test]
+    fn empty() {
+        let layout = assemble_layout("start:\n", &LinkerConfig::default()).unwrap();
+        assert_eq!(Err(Error::Empty), write(&layout))
+    }
+}
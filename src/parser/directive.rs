@@ -0,0 +1,431 @@
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{digit1, space0, space1};
+use nom::combinator::{cut, map, map_opt, map_res, opt, value};
+use nom::error::{context, ErrorKind as NomErrorKind, FromExternalError};
+use nom::multi::separated_list1;
+use nom::sequence::{delimited, preceded, tuple};
+
+use crate::lint::{Level, Lint};
+use crate::object::AddressSize;
+use crate::parser::instruction::operand::address;
+use crate::parser::{valid_word, Error, ErrorKind, IResult, Input};
+
+struct UnknownWarning(String);
+
+impl<'a> FromExternalError<Input<'a>, UnknownWarning> for Error<Input<'a>> {
+    fn from_external_error(input: Input<'a>, kind: NomErrorKind, e: UnknownWarning) -> Self {
+        Error {
+            errors: vec![
+                (input, ErrorKind::Nom(kind)),
+                (input, ErrorKind::UnknownWarning(e.0)),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Directive {
+    Segment(String),
+    Export(Vec<String>),
+    Import(Vec<String>, AddressSize),
+    /// Exported when defined in this module, imported otherwise.
+    Global(Vec<String>, AddressSize),
+    /// Continues at a fixed address instead of in a linker segment.
+    Org(u16),
+    /// The label execution starts at, for output formats that record it. Also spelled `.run`.
+    Entry(String),
+    /// A label to call while the program is being loaded, for formats that support it.
+    Init(String),
+    /// A BASIC line that starts the program with `SYS label`.
+    BasicStub(String),
+    /// The NMI, RESET and IRQ vectors, placed at `$FFFA`.
+    Vectors {
+        nmi: String,
+        reset: String,
+        irq: String,
+    },
+    /// Sets the level of a lint, or of all of them, from here on.
+    Warning(Level, Option<Lint>),
+    /// Starts a region where branches must not cross a page and indexed reads must not be able
+    /// to. Written `.pagecheck` or `.pagecheck count`, where the indexed reads use the first
+    /// `count` index values, between 1 and 256, or all of them if it is left out. Also spelled
+    /// `.timing`.
+    PageCheck(u16),
+    /// Ends a region started with `.pagecheck`. Also spelled `.endtiming`.
+    EndPageCheck,
+    /// Starts a block whose best and worst case cycle counts must both be between `min` and
+    /// `max`. Written `.cycles max` or `.cycles min, max`.
+    Cycles {
+        min: u32,
+        max: u32,
+    },
+    /// Ends a block started with `.cycles` and checks its cycle count.
+    EndCycles,
+}
+
+impl Directive {
+    pub fn parse(i: Input) -> IResult<Self> {
+        context(
+            "Directive",
+            preceded(
+                tuple((space1, tag("."))),
+                alt((
+                    Self::segment,
+                    Self::export,
+                    Self::import,
+                    Self::global,
+                    Self::org,
+                    Self::entry,
+                    Self::init,
+                    Self::basic_stub,
+                    Self::vectors,
+                    Self::warning,
+                    Self::page_check,
+                    Self::cycles,
+                )),
+            ),
+        )(i)
+    }
+
+    fn segment(i: Input) -> IResult<Self> {
+        map(preceded(tuple((tag("segment"), space1)), string), |s| {
+            Directive::Segment(s.to_owned())
+        })(i)
+    }
+
+    fn export(i: Input) -> IResult<Self> {
+        map(
+            preceded(tuple((tag("export"), space1)), symbol_list),
+            Directive::Export,
+        )(i)
+    }
+
+    fn import(i: Input) -> IResult<Self> {
+        map(
+            tuple((
+                preceded(tag("import"), address_size),
+                preceded(space1, symbol_list),
+            )),
+            |(size, symbols)| Directive::Import(symbols, size),
+        )(i)
+    }
+
+    fn global(i: Input) -> IResult<Self> {
+        map(
+            tuple((
+                preceded(tag("global"), address_size),
+                preceded(space1, symbol_list),
+            )),
+            |(size, symbols)| Directive::Global(symbols, size),
+        )(i)
+    }
+
+    fn org(i: Input) -> IResult<Self> {
+        map(
+            preceded(tuple((tag("org"), space1)), address),
+            Directive::Org,
+        )(i)
+    }
+
+    fn basic_stub(i: Input) -> IResult<Self> {
+        map(
+            preceded(tuple((tag("basicstub"), space1)), valid_word),
+            |s| Directive::BasicStub(s.to_owned()),
+        )(i)
+    }
+
+    fn vectors(i: Input) -> IResult<Self> {
+        let separator = || tuple((space0, tag(","), space0));
+        map(
+            preceded(
+                tuple((tag("vectors"), space1)),
+                tuple((
+                    valid_word,
+                    preceded(separator(), valid_word),
+                    preceded(separator(), valid_word),
+                )),
+            ),
+            |(nmi, reset, irq)| Directive::Vectors {
+                nmi: nmi.to_owned(),
+                reset: reset.to_owned(),
+                irq: irq.to_owned(),
+            },
+        )(i)
+    }
+
+    fn warning(i: Input) -> IResult<Self> {
+        fn setting<T: std::str::FromStr>(i: Input) -> IResult<T> {
+            map_res(valid_word, |s: &str| {
+                s.parse().map_err(|_| UnknownWarning(s.to_owned()))
+            })(i)
+        }
+        map(
+            preceded(
+                tuple((tag("warning"), space1)),
+                tuple((setting, opt(preceded(space1, cut(setting))))),
+            ),
+            |(level, lint)| Directive::Warning(level, lint),
+        )(i)
+    }
+
+    fn page_check(i: Input) -> IResult<Self> {
+        alt((
+            map(
+                preceded(
+                    alt((tag("pagecheck"), tag("timing"))),
+                    opt(preceded(
+                        space1,
+                        map_opt(digit1, |s: &str| {
+                            s.parse::<u16>().ok().filter(|n| (1..=0x100).contains(n))
+                        }),
+                    )),
+                ),
+                |count| Directive::PageCheck(count.unwrap_or(0x100)),
+            ),
+            value(
+                Directive::EndPageCheck,
+                alt((tag("endpagecheck"), tag("endtiming"))),
+            ),
+        ))(i)
+    }
+
+    fn cycles(i: Input) -> IResult<Self> {
+        let count = || map_opt(digit1, |s: &str| s.parse::<u32>().ok());
+        alt((
+            map(
+                preceded(
+                    tuple((tag("cycles"), space1)),
+                    tuple((
+                        count(),
+                        opt(preceded(tuple((space0, tag(","), space0)), count())),
+                    )),
+                ),
+                |(first, second)| match second {
+                    Some(max) => Directive::Cycles { min: first, max },
+                    None => Directive::Cycles { min: 0, max: first },
+                },
+            ),
+            value(Directive::EndCycles, tag("endcycles")),
+        ))(i)
+    }
+
+    fn entry(i: Input) -> IResult<Self> {
+        map(
+            preceded(tuple((alt((tag("entry"), tag("run"))), space1)), valid_word),
+            |s| Directive::Entry(s.to_owned()),
+        )(i)
+    }
+
+    fn init(i: Input) -> IResult<Self> {
+        map(preceded(tuple((tag("init"), space1)), valid_word), |s| {
+            Directive::Init(s.to_owned())
+        })(i)
+    }
+}
+
+fn address_size(i: Input) -> IResult<AddressSize> {
+    alt((
+        value(AddressSize::ZeroPage, tag("zp")),
+        value(AddressSize::Absolute, tag("")),
+    ))(i)
+}
+
+fn symbol_list(i: Input) -> IResult<Vec<String>> {
+    context(
+        "SymbolList",
+        separated_list1(
+            tuple((space0, tag(","), space0)),
+            map(valid_word, |s| s.to_owned()),
+        ),
+    )(i)
+}
+
+fn string(i: Input<'_>) -> IResult<'_, &str> {
+    context("String", delimited(tag("\""), valid_word, tag("\"")))(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_success() {
+        let input = "  .segment \"ZEROPAGE\"\n";
+        let result = Directive::parse(input);
+        assert_eq!(
+            Ok(("\n", Directive::Segment("ZEROPAGE".to_owned()))),
+            result
+        )
+    }
+
+    #[test]
+    fn segment_fail() {
+        let input = "  .segment CODE\n";
+        let result = Directive::parse(input);
+        assert!(result.is_err())
+    }
+
+    #[test]
+    fn export_success() {
+        let input = "  .export start, print_char\n";
+        let result = Directive::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Directive::Export(vec!["start".to_owned(), "print_char".to_owned()])
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn import_success() {
+        let input = "  .import print\n";
+        let result = Directive::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Directive::Import(vec!["print".to_owned()], AddressSize::Absolute)
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn importzp_success() {
+        let input = "  .importzp ptr ,tmp\n";
+        let result = Directive::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Directive::Import(
+                    vec!["ptr".to_owned(), "tmp".to_owned()],
+                    AddressSize::ZeroPage
+                )
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn globalzp_success() {
+        let input = "  .globalzp ptr\n";
+        let result = Directive::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Directive::Global(vec!["ptr".to_owned()], AddressSize::ZeroPage)
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn org_success() {
+        let input = "  .org $C000\n";
+        let result = Directive::parse(input);
+        assert_eq!(Ok(("\n", Directive::Org(0xC000))), result)
+    }
+
+    #[test]
+    fn entry_success() {
+        let input = "  .entry start\n";
+        let result = Directive::parse(input);
+        assert_eq!(Ok(("\n", Directive::Entry("start".to_owned()))), result)
+    }
+
+    #[test]
+    fn basic_stub_success() {
+        let input = "  .basicstub start\n";
+        let result = Directive::parse(input);
+        assert_eq!(Ok(("\n", Directive::BasicStub("start".to_owned()))), result)
+    }
+
+    #[test]
+    fn run_success() {
+        let input = "  .run start\n";
+        let result = Directive::parse(input);
+        assert_eq!(Ok(("\n", Directive::Entry("start".to_owned()))), result)
+    }
+
+    #[test]
+    fn init_success() {
+        let input = "  .init setup\n";
+        let result = Directive::parse(input);
+        assert_eq!(Ok(("\n", Directive::Init("setup".to_owned()))), result)
+    }
+
+    #[test]
+    fn vectors_success() {
+        let input = "  .vectors nmi, reset ,irq\n";
+        let result = Directive::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Directive::Vectors {
+                    nmi: "nmi".to_owned(),
+                    reset: "reset".to_owned(),
+                    irq: "irq".to_owned()
+                }
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn vectors_fail() {
+        let input = "  .vectors nmi, reset\n";
+        let result = Directive::parse(input);
+        assert!(result.is_err())
+    }
+
+    #[test]
+    fn warning_success() {
+        let input = "  .warning off unused_label\n";
+        let result = Directive::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Directive::Warning(Level::Allow, Some(Lint::UnusedLabel))
+            )),
+            result
+        );
+        let result = Directive::parse("  .warning deny\n");
+        assert_eq!(Ok(("\n", Directive::Warning(Level::Deny, None))), result)
+    }
+
+    #[test]
+    fn warning_fail() {
+        let input = "  .warning off unused_labels\n";
+        let result = Directive::parse(input);
+        assert!(result.is_err())
+    }
+
+    #[test]
+    fn page_check_success() {
+        let result = Directive::parse("  .timing\n");
+        assert_eq!(Ok(("\n", Directive::PageCheck(0x100))), result);
+        let result = Directive::parse("  .pagecheck 4\n");
+        assert_eq!(Ok(("\n", Directive::PageCheck(4))), result);
+        let result = Directive::parse("  .endpagecheck\n");
+        assert_eq!(Ok(("\n", Directive::EndPageCheck)), result)
+    }
+
+    #[test]
+    fn cycles_success() {
+        let result = Directive::parse("  .cycles 63\n");
+        assert_eq!(Ok(("\n", Directive::Cycles { min: 0, max: 63 })), result);
+        let result = Directive::parse("  .cycles 60, 63\n");
+        assert_eq!(Ok(("\n", Directive::Cycles { min: 60, max: 63 })), result);
+        let result = Directive::parse("  .endcycles\n");
+        assert_eq!(Ok(("\n", Directive::EndCycles)), result)
+    }
+
+    #[test]
+    fn import_fail() {
+        let input = "  .importabs ptr\n";
+        let result = Directive::parse(input);
+        assert!(result.is_err())
+    }
+}
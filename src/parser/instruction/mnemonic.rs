@@ -18,14 +18,40 @@ impl<'a> FromExternalError<Input<'a>, UndefinedMnemonic> for Error<Input<'a>> {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, strum_macros::EnumString, strum_macros::Display)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Eq, PartialEq, Clone, strum_macros::EnumString, strum_macros::Display)]
 pub enum Mnemonic {
     STZ,
     RTS,
     JMP,
+    LDA,
+    BCC,
+    BCS,
+    BEQ,
+    BMI,
+    BNE,
+    BPL,
+    BRA,
+    BVC,
+    BVS,
 }
 
 impl Mnemonic {
+    pub fn is_branch(&self) -> bool {
+        matches!(
+            self,
+            Mnemonic::BCC
+                | Mnemonic::BCS
+                | Mnemonic::BEQ
+                | Mnemonic::BMI
+                | Mnemonic::BNE
+                | Mnemonic::BPL
+                | Mnemonic::BRA
+                | Mnemonic::BVC
+                | Mnemonic::BVS
+        )
+    }
+
     pub fn parse(i: Input) -> IResult<Self> {
         context(
             "Mnemonic",
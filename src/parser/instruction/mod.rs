@@ -25,11 +25,19 @@ impl<'a> FromExternalError<Input<'a>, InvalidAddressingMode> for Error<Input<'a>
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Instruction {
     StzAbsolute(OperandExpression<u16>),
+    StzZeroPage(OperandExpression<u8>),
     RtsStack,
     JmpAbsolute(OperandExpression<u16>),
+    JmpAbsoluteIndirect(OperandExpression<u16>),
+    LdaImmediate(OperandExpression<u8>),
+    LdaZeroPageX(OperandExpression<u8>),
+    LdaAbsoluteX(OperandExpression<u16>),
+    LdaAbsoluteY(OperandExpression<u16>),
+    /// A conditional branch or `BRA`, with the target address.
+    Relative(Mnemonic, OperandExpression<u16>),
 }
 
 impl Instruction {
@@ -41,19 +49,120 @@ impl Instruction {
                 preceded(space1, tuple((Mnemonic::parse, AddressingMode::parse))),
                 |(mnemonic, operand)| match (mnemonic, operand) {
                     (Mnemonic::STZ, AddressingMode::Absolute(a)) => Ok(StzAbsolute(a)),
+                    (Mnemonic::STZ, AddressingMode::ZeroPage(a)) => Ok(StzZeroPage(a)),
                     (Mnemonic::RTS, AddressingMode::NoOperand) => Ok(RtsStack),
                     (Mnemonic::JMP, AddressingMode::Absolute(a)) => Ok(JmpAbsolute(a)),
+                    (Mnemonic::JMP, AddressingMode::AbsoluteIndirect(a)) => {
+                        Ok(JmpAbsoluteIndirect(a))
+                    }
+                    (
+                        Mnemonic::JMP,
+                        AddressingMode::ZeroPageIndirect(OperandExpression::Known(a)),
+                    ) => Ok(JmpAbsoluteIndirect(OperandExpression::Known(a as u16))),
+                    (Mnemonic::LDA, AddressingMode::Immediate(a)) => Ok(LdaImmediate(a)),
+                    (Mnemonic::LDA, AddressingMode::ZeroPageIndexedX(a)) => Ok(LdaZeroPageX(a)),
+                    (Mnemonic::LDA, AddressingMode::AbsoluteIndexedX(a)) => Ok(LdaAbsoluteX(a)),
+                    (Mnemonic::LDA, AddressingMode::AbsoluteIndexedY(a)) => Ok(LdaAbsoluteY(a)),
+                    (
+                        Mnemonic::LDA,
+                        AddressingMode::ZeroPageIndexedY(OperandExpression::Known(a)),
+                    ) => Ok(LdaAbsoluteY(OperandExpression::Known(a as u16))),
+                    (m, AddressingMode::Absolute(a)) if m.is_branch() => Ok(Relative(m, a)),
+                    (m, AddressingMode::ZeroPage(OperandExpression::Known(a))) if m.is_branch() => {
+                        Ok(Relative(m, OperandExpression::Known(a as u16)))
+                    }
                     (mnemonic, operand) => Err(InvalidAddressingMode(mnemonic, operand)),
                 },
             ),
         )(i)
     }
 
+    pub fn mnemonic(&self) -> Mnemonic {
+        match self {
+            Instruction::StzAbsolute(_) | Instruction::StzZeroPage(_) => Mnemonic::STZ,
+            Instruction::RtsStack => Mnemonic::RTS,
+            Instruction::JmpAbsolute(_) | Instruction::JmpAbsoluteIndirect(_) => Mnemonic::JMP,
+            Instruction::LdaImmediate(_)
+            | Instruction::LdaZeroPageX(_)
+            | Instruction::LdaAbsoluteX(_)
+            | Instruction::LdaAbsoluteY(_) => Mnemonic::LDA,
+            Instruction::Relative(mnemonic, _) => mnemonic.clone(),
+        }
+    }
+
     pub fn instruction_byte(&self) -> u8 {
         match self {
             Instruction::StzAbsolute(_) => 0x9C,
+            Instruction::StzZeroPage(_) => 0x64,
             Instruction::RtsStack => 0x60,
             Instruction::JmpAbsolute(_) => 0x4C,
+            Instruction::JmpAbsoluteIndirect(_) => 0x6C,
+            Instruction::LdaImmediate(_) => 0xA9,
+            Instruction::LdaZeroPageX(_) => 0xB5,
+            Instruction::LdaAbsoluteX(_) => 0xBD,
+            Instruction::LdaAbsoluteY(_) => 0xB9,
+            Instruction::Relative(mnemonic, _) => match mnemonic {
+                Mnemonic::BPL => 0x10,
+                Mnemonic::BMI => 0x30,
+                Mnemonic::BVC => 0x50,
+                Mnemonic::BVS => 0x70,
+                Mnemonic::BRA => 0x80,
+                Mnemonic::BCC => 0x90,
+                Mnemonic::BCS => 0xB0,
+                Mnemonic::BNE => 0xD0,
+                Mnemonic::BEQ => 0xF0,
+                _ => unreachable!("Only branches are relative"),
+            },
+        }
+    }
+
+    /// The zero page form of an instruction whose operand turned out to fit in one byte.
+    pub fn zero_page(&self) -> Option<Self> {
+        match self {
+            Instruction::StzAbsolute(OperandExpression::Label(l)) => Some(
+                Instruction::StzZeroPage(OperandExpression::Label(l.clone())),
+            ),
+            Instruction::LdaAbsoluteX(OperandExpression::Label(l)) => Some(
+                Instruction::LdaZeroPageX(OperandExpression::Label(l.clone())),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction reads from its operand plus an index register, which takes an
+    /// extra cycle when that crosses a page.
+    pub fn is_indexed_absolute(&self) -> bool {
+        matches!(
+            self,
+            Instruction::LdaAbsoluteX(_) | Instruction::LdaAbsoluteY(_)
+        )
+    }
+
+    /// The label the operand refers to, if any.
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Instruction::StzAbsolute(ot)
+            | Instruction::JmpAbsolute(ot)
+            | Instruction::JmpAbsoluteIndirect(ot)
+            | Instruction::LdaAbsoluteX(ot)
+            | Instruction::LdaAbsoluteY(ot)
+            | Instruction::Relative(_, ot) => ot.label(),
+            Instruction::StzZeroPage(ot)
+            | Instruction::LdaImmediate(ot)
+            | Instruction::LdaZeroPageX(ot) => ot.label(),
+            Instruction::RtsStack => None,
+        }
+    }
+
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::StzAbsolute(_) => 3,
+            Instruction::StzZeroPage(_) => 2,
+            Instruction::RtsStack => 1,
+            Instruction::JmpAbsolute(_) | Instruction::JmpAbsoluteIndirect(_) => 3,
+            Instruction::LdaImmediate(_) | Instruction::LdaZeroPageX(_) => 2,
+            Instruction::LdaAbsoluteX(_) | Instruction::LdaAbsoluteY(_) => 3,
+            Instruction::Relative(_, _) => 2,
         }
     }
 }
@@ -95,6 +204,88 @@ mod tests {
         )
     }
 
+    #[test]
+    fn instruction_success_4() {
+        let input = "  STZ $12\n";
+        let result = Instruction::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::StzZeroPage(OperandExpression::Known(0x12))
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn instruction_branch() {
+        let input = "  BNE loop\n";
+        let result = Instruction::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::Relative(Mnemonic::BNE, OperandExpression::Label("loop".to_owned()))
+            )),
+            result
+        );
+        let result = Instruction::parse("  BRA $12\n");
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::Relative(Mnemonic::BRA, OperandExpression::Known(0x12))
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn instruction_jmp_indirect() {
+        let result = Instruction::parse("  JMP ($10FF)\n");
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::JmpAbsoluteIndirect(OperandExpression::Known(0x10FF))
+            )),
+            result
+        );
+        let result = Instruction::parse("  JMP ($12)\n");
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::JmpAbsoluteIndirect(OperandExpression::Known(0x12))
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn instruction_indexed() {
+        let result = Instruction::parse("  LDA table,X\n");
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::LdaAbsoluteX(OperandExpression::Label("table".to_owned()))
+            )),
+            result
+        );
+        let result = Instruction::parse("  LDA $12, Y\n");
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::LdaAbsoluteY(OperandExpression::Known(0x12))
+            )),
+            result
+        );
+        let result = Instruction::parse("  LDA $12,X\n");
+        assert_eq!(
+            Ok((
+                "\n",
+                Instruction::LdaZeroPageX(OperandExpression::Known(0x12))
+            )),
+            result
+        )
+    }
+
     #[test]
     fn instruction_fail() {
         let input = "090";
@@ -1,7 +1,7 @@
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take};
 use nom::character::complete::{hex_digit1, space0, space1};
-use nom::combinator::{map, map_parser, map_res, success};
+use nom::combinator::{all_consuming, map, map_parser, map_res, success};
 use nom::error::{context, ErrorKind as NomErrorKind, FromExternalError};
 use nom::sequence::{delimited, preceded, terminated, tuple};
 
@@ -157,7 +157,7 @@ impl Number {
 
     fn parse_8bit(i: Input) -> IResult<Self> {
         map(
-            map_parser(hex_digit1, take(2usize)),
+            map_parser(hex_digit1, all_consuming(take(2usize))),
             // TODO from_str_radix should be safe since we parse for hex digits. Maybe implement custom error?
             |s| Number::N8b(u8::from_str_radix(s, 16).expect("Parser returned non-hex bytes?")),
         )(i)
@@ -165,7 +165,7 @@ impl Number {
 
     fn parse_16bit(i: Input) -> IResult<Self> {
         map(
-            map_parser(hex_digit1, take(4usize)),
+            map_parser(hex_digit1, all_consuming(take(4usize))),
             // TODO from_str_radix should be safe since we parse for hex digits. Maybe implement custom error?
             |s| Number::N16b(u16::from_str_radix(s, 16).expect("Parser returned non-hex bytes?")),
         )(i)
@@ -176,6 +176,21 @@ impl Number {
 pub enum OperandExpression<T> {
     Known(T),
     Label(String),
+    /// `<label`, the low byte of the label's address.
+    LowByte(String),
+    /// `>label`, the high byte of the label's address.
+    HighByte(String),
+}
+
+impl<T> OperandExpression<T> {
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            OperandExpression::Known(_) => None,
+            OperandExpression::Label(l)
+            | OperandExpression::LowByte(l)
+            | OperandExpression::HighByte(l) => Some(l),
+        }
+    }
 }
 
 // TODO any way to get this inside the impl block?
@@ -185,6 +200,7 @@ fn parse_operand_expression(
     context(
         "OperandExpression",
         alt((
+            map(parse_byte_selection, Either::Left),
             map(valid_word, |l| {
                 Either::Right(OperandExpression::Label(l.to_owned()))
             }),
@@ -196,6 +212,36 @@ fn parse_operand_expression(
     )(i)
 }
 
+/// A number of either width, as an address.
+pub(crate) fn address(i: Input) -> IResult<u16> {
+    map(Number::parse, |n| match n {
+        Number::N8b(n) => n as u16,
+        Number::N16b(n) => n,
+    })(i)
+}
+
+fn parse_byte_selection(i: Input) -> IResult<OperandExpression<u8>> {
+    let word = || {
+        alt((
+            map(valid_word, |l| Either::Left(l.to_owned())),
+            map(address, Either::Right),
+        ))
+    };
+    context(
+        "ByteSelection",
+        alt((
+            map(preceded(tag("<"), word()), |r| match r {
+                Either::Left(l) => OperandExpression::LowByte(l),
+                Either::Right(n) => OperandExpression::Known(n.to_le_bytes()[0]),
+            }),
+            map(preceded(tag(">"), word()), |r| match r {
+                Either::Left(l) => OperandExpression::HighByte(l),
+                Either::Right(n) => OperandExpression::Known(n.to_le_bytes()[1]),
+            }),
+        )),
+    )(i)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -394,4 +440,40 @@ mod tests {
             result
         )
     }
+
+    #[test]
+    fn low_byte_success() {
+        let input = " #<table\n";
+        let result = AddressingMode::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                AddressingMode::Immediate(OperandExpression::LowByte("table".to_owned()))
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn high_byte_success() {
+        let input = " #>$1234\n";
+        let result = AddressingMode::parse(input);
+        assert_eq!(
+            Ok((
+                "\n",
+                AddressingMode::Immediate(OperandExpression::Known(0x12))
+            )),
+            result
+        )
+    }
+
+    #[test]
+    fn number_too_long() {
+        let input = " $12345\n";
+        let result = AddressingMode::parse(input);
+        assert_eq!(Ok((input, AddressingMode::NoOperand)), result);
+        let input = " $123\n";
+        let result = AddressingMode::parse(input);
+        assert_eq!(Ok((input, AddressingMode::NoOperand)), result)
+    }
 }
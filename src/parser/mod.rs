@@ -3,20 +3,22 @@ use std::fmt;
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::character::complete::{alpha1, alphanumeric1, newline};
-use nom::combinator::{all_consuming, map, recognize};
+use nom::combinator::{map, recognize, rest_len};
 use nom::error::{context, ContextError, ErrorKind as NomErrorKind, ParseError as NomParseError};
 use nom::multi::{many0, many1};
-use nom::sequence::{delimited, terminated, tuple};
+use nom::sequence::{terminated, tuple};
 use nom::Finish;
 
-use instruction::mnemonic::Mnemonic;
+use crate::diagnostic::{Diagnostic, Severity, Span};
+pub use directive::Directive;
+pub use instruction::mnemonic::Mnemonic;
 pub use instruction::operand::{AddressingMode, OperandExpression};
 pub use instruction::Instruction;
 
+mod directive;
 mod instruction;
 
 pub type Input<'a> = &'a str;
-pub type Result<'a, T> = std::result::Result<T, Error<Input<'a>>>;
 
 type IResult<'a, T> = nom::IResult<Input<'a>, T, Error<Input<'a>>>;
 
@@ -35,9 +37,9 @@ impl<'a> Error<Input<'a>> {
                 ErrorKind::UndefinedMnemonic(m) => format!("undefined mnemonic \"{}\"", m),
                 ErrorKind::InvalidAddressingMode(m, o) => format!("invalid mode: {}, {}", m, o),
                 ErrorKind::OperandTooLong(n) => format!("operand too long: {}", n),
+                ErrorKind::UnknownWarning(w) => format!("unknown warning setting \"{}\"", w),
             };
 
-            // TODO better way to do line numbering
             let input: String = take_until_newline(input);
             writeln!(f, "{:<40} \"{}\"", prefix, input)?;
         }
@@ -46,6 +48,43 @@ impl<'a> Error<Input<'a>> {
     }
 }
 
+impl<'a> Error<Input<'a>> {
+    /// Points at the line that could not be parsed, with the most specific error found.
+    pub fn diagnostic(&self, source: &str) -> Diagnostic {
+        let specific = self
+            .errors
+            .iter()
+            .find(|(_, kind)| !matches!(kind, ErrorKind::Nom(_) | ErrorKind::Context(_)));
+        let (input, kind) = specific
+            .or_else(|| self.errors.first())
+            .expect("Empty error");
+        let message = match kind {
+            ErrorKind::UndefinedMnemonic(m) => format!("undefined mnemonic {}", m),
+            ErrorKind::InvalidAddressingMode(m, o) => {
+                format!("{} does not support {} addressing", m, o)
+            }
+            ErrorKind::OperandTooLong(_) => "operand does not fit in one byte".to_owned(),
+            ErrorKind::UnknownWarning(w) => format!("unknown lint or level {}", w),
+            ErrorKind::Nom(_) | ErrorKind::Context(_) => {
+                "expected a label, directive or instruction".to_owned()
+            }
+        };
+        let start = source.len() - input.len();
+        let line = take_until_newline(input);
+        let start = start + (line.len() - line.trim_start().len());
+        let length = match kind {
+            ErrorKind::UndefinedMnemonic(m) | ErrorKind::UnknownWarning(m) => m.len(),
+            _ => line.trim().len(),
+        };
+        Diagnostic {
+            message,
+            span: Span::new(start, start + length),
+            severity: Severity::Error,
+            module: 0,
+        }
+    }
+}
+
 impl<'a> std::error::Error for Error<Input<'a>> {}
 
 impl<'a> fmt::Display for Error<Input<'a>> {
@@ -92,23 +131,42 @@ pub enum ErrorKind {
     InvalidAddressingMode(Mnemonic, AddressingMode),
     UndefinedMnemonic(String),
     OperandTooLong(OperandExpression<u16>),
+    UnknownWarning(String),
 }
 
 fn take_until_newline(input: &str) -> String {
     input.chars().take_while(|&c| c != '\n').collect()
 }
 
+/// A parsed value and where it is in the source.
 #[derive(Debug, Eq, PartialEq)]
-pub struct Parsed(pub Vec<Element>);
+pub struct Located<T> {
+    pub span: Span,
+    /// The operand of an instruction or the arguments of a directive.
+    pub operand: Option<Span>,
+    pub value: T,
+}
+
+/// Instructions and directives are a word followed by their operand, if any.
+fn operand_span(text: &str, start: usize) -> Option<Span> {
+    let name = text.trim_start();
+    let name_start = text.len() - name.len();
+    let operand_start = name_start + name.find(char::is_whitespace)?;
+    let operand = text[operand_start..].trim_start();
+    let offset = start + text.len() - operand.len();
+    Some(Span::new(offset, offset + operand.len())).filter(|_| !operand.is_empty())
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Parsed(pub Vec<Located<Element>>);
 
 impl Parsed {
-    fn parse(i: Input) -> IResult<Self> {
+    /// One element and the newlines after it, with the remaining input lengths before and after
+    /// the element.
+    fn line(i: Input) -> IResult<(usize, Element, usize)> {
         context(
-            "File",
-            all_consuming(map(
-                many0(delimited(many0(newline), Element::parse, many1(newline))),
-                Self,
-            )),
+            "Line",
+            terminated(tuple((rest_len, Element::parse, rest_len)), many1(newline)),
         )(i)
     }
 }
@@ -117,6 +175,7 @@ impl Parsed {
 pub enum Element {
     Instruction(instruction::Instruction),
     Label(String),
+    Directive(Directive),
 }
 
 impl Element {
@@ -125,6 +184,7 @@ impl Element {
             "Element",
             alt((
                 map(Label::parse, Element::Label),
+                map(Directive::parse, Element::Directive),
                 map(instruction::Instruction::parse, Element::Instruction),
             )),
         )(i)
@@ -144,21 +204,51 @@ impl Label {
     }
 }
 
-pub fn parse(i: Input) -> Result<Parsed> {
-    Finish::finish(Parsed::parse(i)).map(|(_i, p)| p)
+/// Parses every line, skipping the ones that have errors, until `max_errors` errors are found.
+/// A limit of 0 means all of them.
+pub fn parse(i: Input, max_errors: usize) -> (Parsed, Vec<Error<Input>>) {
+    let mut elements = vec![];
+    let mut errors = vec![];
+    let mut rest = i.trim_start_matches('\n');
+    while !rest.is_empty() {
+        match Finish::finish(Parsed::line(rest)) {
+            Ok((next, (before, value, after))) => {
+                let span = Span::new(i.len() - before, i.len() - after);
+                let operand = match value {
+                    Element::Label(_) => None,
+                    _ => operand_span(&i[span.start..span.end], span.start),
+                };
+                elements.push(Located {
+                    span,
+                    operand,
+                    value,
+                });
+                rest = next;
+            }
+            Err(e) => {
+                errors.push(e);
+                if errors.len() == max_errors {
+                    break;
+                }
+                rest = rest.find('\n').map_or("", |idx| &rest[idx + 1..]);
+            }
+        }
+        rest = rest.trim_start_matches('\n');
+    }
+    (Parsed(elements), errors)
 }
 
-fn valid_word(i: Input) -> IResult<&str> {
+fn valid_word(i: Input<'_>) -> IResult<'_, &str> {
     dbg!(i);
     context("valid_word", recognize(tuple((valid_start, valid_end))))(i)
 }
 
-fn valid_start(i: Input) -> IResult<&str> {
+fn valid_start(i: Input<'_>) -> IResult<'_, &str> {
     dbg!(i);
     context("valid_start", alpha1)(i)
 }
 
-fn valid_end(i: Input) -> IResult<&str> {
+fn valid_end(i: Input<'_>) -> IResult<'_, &str> {
     dbg!(i);
     context(
         "valid_end",
@@ -170,6 +260,14 @@ fn valid_end(i: Input) -> IResult<&str> {
 mod tests {
     use super::*;
 
+    fn located<T>(span: (usize, usize), operand: Option<(usize, usize)>, value: T) -> Located<T> {
+        Located {
+            span: Span::new(span.0, span.1),
+            operand: operand.map(|(start, end)| Span::new(start, end)),
+            value,
+        }
+    }
+
     #[test]
     fn valid_start_success() {
         let input = "abcD_0e x";
@@ -238,12 +336,21 @@ mod tests {
     #[test]
     fn parse_success_1() {
         let input = "  STZ $0300\n  RTS\n";
-        let result = parse(input);
+        let result = parse(input, 0);
         assert_eq!(
-            Ok(Parsed(vec![
-                Element::Instruction(Instruction::StzAbsolute(OperandExpression::Known(0x300))),
-                Element::Instruction(Instruction::RtsStack),
-            ])),
+            (
+                Parsed(vec![
+                    located(
+                        (0, 11),
+                        Some((6, 11)),
+                        Element::Instruction(Instruction::StzAbsolute(OperandExpression::Known(
+                            0x300
+                        )))
+                    ),
+                    located((12, 17), None, Element::Instruction(Instruction::RtsStack)),
+                ]),
+                vec![]
+            ),
             result
         )
     }
@@ -251,12 +358,42 @@ mod tests {
     #[test]
     fn parse_success_2() {
         let input = "\n\n  STZ $0300\n\n\n  RTS\n\n";
-        let result = parse(input);
+        let result = parse(input, 0);
         assert_eq!(
-            Ok(Parsed(vec![
-                Element::Instruction(Instruction::StzAbsolute(OperandExpression::Known(0x300))),
-                Element::Instruction(Instruction::RtsStack),
-            ])),
+            (
+                Parsed(vec![
+                    located(
+                        (2, 13),
+                        Some((8, 13)),
+                        Element::Instruction(Instruction::StzAbsolute(OperandExpression::Known(
+                            0x300
+                        )))
+                    ),
+                    located((16, 21), None, Element::Instruction(Instruction::RtsStack)),
+                ]),
+                vec![]
+            ),
+            result
+        )
+    }
+
+    #[test]
+    fn parse_success_3() {
+        let input = "  .segment \"DATA\"\nlabel:\n  RTS\n";
+        let result = parse(input, 0);
+        assert_eq!(
+            (
+                Parsed(vec![
+                    located(
+                        (0, 17),
+                        Some((11, 17)),
+                        Element::Directive(Directive::Segment("DATA".to_owned()))
+                    ),
+                    located((18, 24), None, Element::Label("label".to_owned())),
+                    located((25, 30), None, Element::Instruction(Instruction::RtsStack)),
+                ]),
+                vec![]
+            ),
             result
         )
     }
@@ -264,7 +401,50 @@ mod tests {
     #[test]
     fn parse_fail_1() {
         let input = "  STZ $0300\n  RTS";
-        let result = parse(input);
-        assert!(result.is_err())
+        let (_, errors) = parse(input, 0);
+        assert_eq!(1, errors.len())
+    }
+
+    #[test]
+    fn parse_fail_diagnostic() {
+        let input = "  RTS\n  STA $0300\n";
+        let (_, errors) = parse(input, 0);
+        assert_eq!(
+            Diagnostic {
+                message: "undefined mnemonic STA".to_owned(),
+                span: Span::new(8, 11),
+                severity: Severity::Error,
+                module: 0,
+            },
+            errors[0].diagnostic(input)
+        )
+    }
+
+    #[test]
+    fn parse_recovers() {
+        let input = "  STA $0300\nstart:\n  .foo\n  JMP start\n  LDA ($12)\n  RTS\n";
+        let (parsed, errors) = parse(input, 0);
+        assert_eq!(
+            vec![
+                Element::Label("start".to_owned()),
+                Element::Instruction(Instruction::JmpAbsolute(OperandExpression::Label(
+                    "start".to_owned()
+                ))),
+                Element::Instruction(Instruction::RtsStack),
+            ],
+            parsed.0.into_iter().map(|l| l.value).collect::<Vec<_>>()
+        );
+        let spans: Vec<_> = errors.iter().map(|e| e.diagnostic(input).span).collect();
+        assert_eq!(
+            vec![Span::new(2, 5), Span::new(21, 25), Span::new(40, 49)],
+            spans
+        )
+    }
+
+    #[test]
+    fn parse_max_errors() {
+        let input = "  .foo\n  .bar\n  .baz\n";
+        let (_, errors) = parse(input, 2);
+        assert_eq!(2, errors.len())
     }
 }
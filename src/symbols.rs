@@ -0,0 +1,177 @@
+//! Symbol table files for emulators, debuggers and scripts.
+//!
+//! Every writer lists the symbols of a [`Layout`] sorted by address, then by name, so private
+//! labels with the same name in different modules each get their own entry.
+
+use std::fmt::Write;
+
+use crate::linker::{Layout, PlacedSymbol};
+
+fn sorted(layout: &Layout) -> Vec<&PlacedSymbol> {
+    let mut symbols: Vec<_> = layout.symbols.iter().collect();
+    symbols.sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+    symbols
+}
+
+/// `name = $addr`, one symbol per line.
+pub fn plain(layout: &Layout) -> String {
+    let mut out = String::new();
+    for symbol in sorted(layout) {
+        writeln!(out, "{} = ${:04X}", symbol.name, symbol.address).unwrap();
+    }
+    out
+}
+
+/// A VICE monitor label file, loaded with `ll` or `-moncommands`.
+pub fn vice(layout: &Layout) -> String {
+    let mut out = String::new();
+    for symbol in sorted(layout) {
+        writeln!(out, "al C:{:04X} .{}", symbol.address, symbol.name).unwrap();
+    }
+    out
+}
+
+/// A JSON array of `{"name", "segment", "address", "exported"}` objects.
+pub fn json(layout: &Layout) -> String {
+    let entries: Vec<String> = sorted(layout)
+        .iter()
+        .map(|symbol| {
+            format!(
+                "  {{\"name\": {}, \"segment\": {}, \"address\": {}, \"exported\": {}}}",
+                json_string(&symbol.name),
+                json_string(&symbol.segment),
+                symbol.address,
+                symbol.exported
+            )
+        })
+        .collect();
+    if entries.is_empty() {
+        "[]\n".to_owned()
+    } else {
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A debug info file in the format ld65 writes with `--dbgfile`, as read by Mesen and other
+/// tools that understand cc65 debug info. Only the segment and symbol records are written, as
+/// there is no source file or scope information to go with them.
+pub fn ca65_dbg(layout: &Layout) -> String {
+    let symbols = sorted(layout);
+    let mut out = String::new();
+    writeln!(out, "version\tmajor=2,minor=0").unwrap();
+    writeln!(
+        out,
+        "info\tcsym=0,file=0,lib=0,line=0,mod=0,scope=0,seg={},span=0,sym={},type=0",
+        layout.segments.len(),
+        symbols.len()
+    )
+    .unwrap();
+    for (id, segment) in layout.segments.iter().enumerate() {
+        let addrsize = address_size(segment.start as usize + segment.bytes.len() <= 0x100);
+        writeln!(
+            out,
+            "seg\tid={},name=\"{}\",start=0x{:06X},size=0x{:04X},addrsize={},type=rw",
+            id,
+            segment.name,
+            segment.start,
+            segment.bytes.len(),
+            addrsize
+        )
+        .unwrap();
+    }
+    for (id, symbol) in symbols.iter().enumerate() {
+        let addrsize = address_size(symbol.address <= 0xFF);
+        let seg = layout
+            .segments
+            .iter()
+            .position(|s| s.name == symbol.segment)
+            .expect("Symbol without segment");
+        writeln!(
+            out,
+            "sym\tid={},name=\"{}\",addrsize={},val=0x{:X},seg={},type=lab",
+            id, symbol.name, addrsize, symbol.address, seg
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn address_size(zero_page: bool) -> &'static str {
+    if zero_page {
+        "zeropage"
+    } else {
+        "absolute"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_layout;
+    use crate::linker::LinkerConfig;
+
+    fn layout() -> Layout {
+        let config: LinkerConfig =
+            "ZEROPAGE: start = $0000, size = $0100;\nCODE: start = $8000, size = $1000;"
+                .parse()
+                .unwrap();
+        assemble_layout(
+            "  .export start\nstart:\n  STZ ptr\nloop:\n  JMP loop\n  .segment \"ZEROPAGE\"\n  STZ $00\nptr:\n",
+            &config,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn plain_success() {
+        assert_eq!(
+            "ptr = $0002\nstart = $8000\nloop = $8003\n",
+            plain(&layout())
+        )
+    }
+
+    #[test]
+    fn vice_success() {
+        assert_eq!(
+            "al C:0002 .ptr\nal C:8000 .start\nal C:8003 .loop\n",
+            vice(&layout())
+        )
+    }
+
+    #[test]
+    fn json_success() {
+        let expected = "[\n  {\"name\": \"ptr\", \"segment\": \"ZEROPAGE\", \"address\": 2, \"exported\": false},\n  {\"name\": \"start\", \"segment\": \"CODE\", \"address\": 32768, \"exported\": true},\n  {\"name\": \"loop\", \"segment\": \"CODE\", \"address\": 32771, \"exported\": false}\n]\n";
+        assert_eq!(expected, json(&layout()))
+    }
+
+    #[test]
+    fn json_escape() {
+        assert_eq!("\"a\\\"b\\\\c\\u000a\"", json_string("a\"b\\c\n"))
+    }
+
+    #[test]
+    fn ca65_dbg_success() {
+        let expected = "version\tmajor=2,minor=0\n\
+            info\tcsym=0,file=0,lib=0,line=0,mod=0,scope=0,seg=2,span=0,sym=3,type=0\n\
+            seg\tid=0,name=\"ZEROPAGE\",start=0x000000,size=0x0002,addrsize=zeropage,type=rw\n\
+            seg\tid=1,name=\"CODE\",start=0x008000,size=0x0006,addrsize=absolute,type=rw\n\
+            sym\tid=0,name=\"ptr\",addrsize=zeropage,val=0x2,seg=0,type=lab\n\
+            sym\tid=1,name=\"start\",addrsize=absolute,val=0x8000,seg=1,type=lab\n\
+            sym\tid=2,name=\"loop\",addrsize=absolute,val=0x8003,seg=1,type=lab\n";
+        assert_eq!(expected, ca65_dbg(&layout()))
+    }
+}